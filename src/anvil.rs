@@ -1,8 +1,119 @@
-use ethers::utils::Anvil;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::utils::{hex, Anvil, AnvilInstance};
+use crate::contract_adapter::DeployerConfig;
+use crate::errors::ZkpError;
 
-pub struct OKCNode {
+/// A local devnet, either an anvil launched by this process or an already running node.
+/// A launched anvil is killed when the `LocalNode` is dropped.
+pub struct LocalNode {
+    http_url: String,
+    ws_url: String,
+    chain_id: Option<u64>,
+    wallets: Vec<LocalWallet>,
+    anvil: Option<AnvilInstance>,
+}
+
+impl LocalNode {
+    /// Launches anvil with its default funded accounts, panics when `anvil` isn't on the PATH.
+    pub fn launch() -> Self {
+        Self::launch_with(Anvil::new())
+    }
+
+    /// Launches a customised anvil, e.g. with a block time or a fork url.
+    pub fn launch_with(anvil: Anvil) -> Self {
+        let anvil = anvil.spawn();
+        let chain_id = anvil.chain_id();
+        let wallets = anvil.keys().iter().map(|key| LocalWallet::from(key.clone()).with_chain_id(chain_id)).collect();
+        Self { http_url: anvil.endpoint(), ws_url: anvil.ws_endpoint(), chain_id: Some(chain_id), wallets, anvil: Some(anvil) }
+    }
+
+    /// Uses a node that is already running, `private_keys` are hex keys of accounts funded on it.
+    pub fn attach(http_url: impl Into<String>, ws_url: impl Into<String>, private_keys: &[&str]) -> Result<Self, ZkpError> {
+        let wallets = private_keys.iter().map(|key| {
+            key.trim().trim_start_matches("0x").parse::<LocalWallet>().map_err(|e| {
+                ZkpError::InvalidDeployerConfig(format!("private key: {}", e))
+            })
+        }).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { http_url: http_url.into(), ws_url: ws_url.into(), chain_id: None, wallets, anvil: None })
+    }
+
+    /// `ZKP_LOCAL_NODE=anvil` launches anvil, an http url attaches to that node instead with its ws url taken
+    /// from `ZKP_LOCAL_NODE_WS` and comma separated funded keys from `ZKP_LOCAL_NODE_KEYS`. Unset means no node.
+    pub fn from_env() -> Result<Option<Self>, ZkpError> {
+        let node = match std::env::var("ZKP_LOCAL_NODE") {
+            Ok(node) => node,
+            Err(_) => return Ok(None),
+        };
+        if node == "anvil" {
+            return Ok(Some(Self::launch()));
+        }
+        let ws_url = std::env::var("ZKP_LOCAL_NODE_WS").unwrap_or(node.replacen("http", "ws", 1));
+        let keys = std::env::var("ZKP_LOCAL_NODE_KEYS").unwrap_or_default();
+        let keys: Vec<&str> = keys.split(',').filter(|key| !key.trim().is_empty()).collect();
+        Self::attach(node, ws_url, keys.as_slice()).map(Some)
+    }
+
+    /// The node configured by `from_env`, or a freshly launched anvil.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self::from_env().expect("invalid local node config").unwrap_or_else(Self::launch)
+    }
+
+    pub fn http_url(&self) -> &str {
+        self.http_url.as_str()
+    }
 
+    pub fn ws_url(&self) -> &str {
+        self.ws_url.as_str()
+    }
+
+    /// Known for a launched anvil, an attached node is asked when it is needed.
+    pub fn chain_id(&self) -> Option<u64> {
+        self.chain_id
+    }
+
+    pub fn wallets(&self) -> &[LocalWallet] {
+        self.wallets.as_slice()
+    }
+
+    pub fn is_launched(&self) -> bool {
+        self.anvil.is_some()
+    }
+
+    /// Deploys from the first funded wallet.
+    pub fn deployer_config(&self) -> Result<DeployerConfig, ZkpError> {
+        let wallet = self.wallets.first().ok_or_else(|| {
+            ZkpError::InvalidDeployerConfig(format!("node {} has no funded wallet", self.http_url))
+        })?;
+        let config = DeployerConfig::new(self.http_url.clone(), hex::encode(wallet.signer().to_bytes()));
+        Ok(match self.chain_id {
+            Some(chain_id) => config.with_chain_id(chain_id),
+            None => config,
+        })
+    }
 }
 
+#[test]
+pub fn test_attach() {
+    // anvil's first default account
+    let key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    let node = LocalNode::attach("http://127.0.0.1:8545", "ws://127.0.0.1:8545", &[key]).expect("fail to attach");
+    assert!(!node.is_launched());
+    assert_eq!(node.wallets()[0].address(), "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266".parse().unwrap());
+    let config = node.deployer_config().expect("no deployer config");
+    assert_eq!((config.rpc_url.as_str(), config.private_key.as_str(), config.chain_id), ("http://127.0.0.1:8545", key, None));
 
+    let empty = LocalNode::attach("http://127.0.0.1:8545", "ws://127.0.0.1:8545", &[]).unwrap();
+    assert!(matches!(empty.deployer_config(), Err(ZkpError::InvalidDeployerConfig(_))));
+    assert!(LocalNode::attach("http://127.0.0.1:8545", "ws://127.0.0.1:8545", &["not a key"]).is_err());
+}
 
+#[test]
+#[ignore = "needs anvil"]
+pub fn test_launch() {
+    let node = LocalNode::launch();
+    assert!(node.is_launched());
+    assert!(!node.wallets().is_empty());
+    assert_eq!(node.chain_id(), node.wallets()[0].chain_id().into());
+    assert!(node.ws_url().starts_with("ws://"));
+}
@@ -0,0 +1,156 @@
+use rocket::{Data, Request, response};
+use rocket::http::{ContentType, Status};
+use rocket::response::Responder;
+use rocket::serde::json::{json, Value};
+use rocket_multipart_form_data::Repetition;
+use serde::Serialize;
+use rocket_multipart_form_data::{MultipartFormData, MultipartFormDataField, MultipartFormDataOptions};
+use crate::errors::ZkpError;
+
+// upper bound on witnesses / proofs accepted by a single batch request
+pub const MAX_BATCH_SIZE: usize = 256;
+
+/// One entry of a batch response: `{ "ok": ... }` or `{ "error": { "code", "message" } }`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItem<T> {
+    Ok(T),
+    Error { code: &'static str, message: String },
+}
+
+impl<T> From<Result<T, ZkpError>> for BatchItem<T> {
+    fn from(res: Result<T, ZkpError>) -> Self {
+        match res {
+            Ok(v) => BatchItem::Ok(v),
+            Err(e) => BatchItem::Error { code: e.code(), message: e.to_string() },
+        }
+    }
+}
+
+/// A verification key export, raw bellman bytes or one of the json layouts.
+#[derive(Responder)]
+pub enum VkResponse {
+    Bin(Vec<u8>),
+    Json(Value),
+}
+
+/// Error returned by every endpoint, rendered as `{ "error": { "code", "message" } }`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into() }
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Status::BadRequest, code, message)
+    }
+}
+
+impl From<ZkpError> for ApiError {
+    fn from(e: ZkpError) -> Self {
+        let status = match e {
+            ZkpError::UnknownCircuit(_) | ZkpError::UnknownJob(_) | ZkpError::UnknownSubscription(_) => Status::NotFound,
+            ZkpError::JobNotCancellable(_) | ZkpError::CircuitExists(_) | ZkpError::NotDeployed(_) | ZkpError::SubscriptionExists(_) => Status::Conflict,
+            ZkpError::InvalidR1cs(_)
+            | ZkpError::InvalidCircuitKey(_)
+            | ZkpError::InvalidWitness(_)
+            | ZkpError::WitnessSizeMismatch { .. }
+            | ZkpError::UnsatisfiedConstraint { .. }
+            | ZkpError::InvalidProof(_)
+            | ZkpError::InvalidVerificationKey(_)
+            | ZkpError::InvalidInputs(_)
+            | ZkpError::SetupTooSmall { .. }
+            | ZkpError::InvalidWasm(_)
+            | ZkpError::NoWitnessCalculator(_)
+            | ZkpError::WitnessCalculationFailed(_)
+            | ZkpError::InvalidEventAbi(_) => Status::UnprocessableEntity,
+            ZkpError::WorkerUnavailable(_) | ZkpError::DeployerNotConfigured => Status::ServiceUnavailable,
+            ZkpError::DeployFailed(_) | ZkpError::ChainRequestFailed(_) => Status::BadGateway,
+            ZkpError::ProvingFailed(_)
+            | ZkpError::ProofSelfCheckFailed
+            | ZkpError::InvalidDeployerConfig(_)
+            | ZkpError::CompilationFailed(_)
+            | ZkpError::EventHandlerFailed(_)
+            | ZkpError::Io(_) => Status::InternalServerError,
+        };
+        Self::new(status, e.code(), e.to_string())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = json!({ "error": { "code": self.code, "message": self.message } });
+        (self.status, body).respond_to(req)
+    }
+}
+
+/// Keeps rocket's own failures (unknown routes, rejected data guards) in the same json envelope.
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request) -> ApiError {
+    ApiError::new(status, "http_error", status.reason().unwrap_or("unknown error"))
+}
+
+pub async fn parse_form(content_type: &ContentType, data: Data<'_>, fields: Vec<MultipartFormDataField<'_>>) -> Result<MultipartFormData, ApiError> {
+    let options = MultipartFormDataOptions::with_multipart_form_data_fields(fields);
+    MultipartFormData::parse(content_type, data, options).await.map_err(|e| {
+        ApiError::bad_request("invalid_form", e.to_string())
+    })
+}
+
+pub fn take_text(form: &mut MultipartFormData, name: &str) -> Result<String, ApiError> {
+    take_optional_text(form, name)
+        .ok_or_else(|| ApiError::bad_request("missing_field", format!("form field {} is required", name)))
+}
+
+/// Every occurrence of a repeated text field, in upload order.
+pub fn take_all_text(form: &mut MultipartFormData, name: &str) -> Result<Vec<String>, ApiError> {
+    let fields: Vec<String> = form.texts.remove(name).unwrap_or_default().into_iter().map(|field| field.text).collect();
+    if fields.is_empty() {
+        return Err(ApiError::bad_request("missing_field", format!("form field {} is required", name)));
+    }
+    if fields.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::bad_request("batch_too_large", format!("at most {} {} fields are accepted", MAX_BATCH_SIZE, name)));
+    }
+    Ok(fields)
+}
+
+pub fn take_optional_raw(form: &mut MultipartFormData, name: &str) -> Option<Vec<u8>> {
+    form.raw.get_mut(name)
+        .filter(|fields| !fields.is_empty())
+        .map(|fields| fields.remove(0).raw)
+}
+
+pub fn take_optional_text(form: &mut MultipartFormData, name: &str) -> Option<String> {
+    form.texts.get_mut(name)
+        .filter(|fields| !fields.is_empty())
+        .map(|fields| fields.remove(0).text)
+}
+
+/// Every occurrence of a repeated file field, in upload order.
+pub fn take_all_raw(form: &mut MultipartFormData, name: &str) -> Result<Vec<Vec<u8>>, ApiError> {
+    let fields: Vec<Vec<u8>> = form.raw.remove(name).unwrap_or_default().into_iter().map(|field| field.raw).collect();
+    if fields.is_empty() {
+        return Err(ApiError::bad_request("missing_field", format!("form field {} is required", name)));
+    }
+    if fields.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::bad_request("batch_too_large", format!("at most {} {} fields are accepted", MAX_BATCH_SIZE, name)));
+    }
+    Ok(fields)
+}
+
+pub fn repeated(field: MultipartFormDataField<'_>) -> MultipartFormDataField<'_> {
+    field.repetition(Repetition::infinite())
+}
+
+pub fn take_raw(form: &mut MultipartFormData, name: &str) -> Result<Vec<u8>, ApiError> {
+    form.raw.get_mut(name)
+        .filter(|fields| !fields.is_empty())
+        .map(|fields| fields.remove(0).raw)
+        .ok_or_else(|| ApiError::bad_request("missing_field", format!("form field {} is required", name)))
+}
@@ -0,0 +1,52 @@
+use plonkit::bellman_ce::{CurveAffine, CurveProjective, Engine, Field, PrimeField};
+use plonkit::bellman_ce::bn256::{Bn256, Fr, G1, G1Affine};
+use plonkit::bellman_ce::plonk::better_cs::cs::PlonkCsWidth4WithNextStepParams;
+use plonkit::bellman_ce::plonk::better_cs::verifier::verify_and_aggregate;
+use plonkit::bellman_ce::plonk::commitments::transcript::{Prng, Transcript};
+use plonkit::bellman_ce::plonk::commitments::transcript::keccak_transcript::RollingKeccakTranscript;
+use plonkit::bellman_ce::plonk::{Proof, VerificationKey};
+use crate::errors::ZkpError;
+
+/// The two KZG pairing inputs of a proof, the ones `plonk::verify` pairs with the vk's g2 elements.
+pub type Aggregate = [G1Affine; 2];
+
+/// Reduces a proof to its two KZG pairing inputs, independent of the other proofs so it can run on any thread.
+/// `None` when the proof already fails the checks that come before the pairing.
+pub fn aggregate(
+    vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>,
+    proof: &Proof<Bn256, PlonkCsWidth4WithNextStepParams>,
+) -> Result<Option<Aggregate>, ZkpError> {
+    let (valid, aggregate) = verify_and_aggregate::<_, _, RollingKeccakTranscript<Fr>>(proof, vk, None).map_err(|e| {
+        ZkpError::InvalidProof(e.to_string())
+    })?;
+    Ok(if valid { Some(aggregate) } else { None })
+}
+
+/// Folds the `aggregate` of every proof, in the order of `proofs`, with challenges drawn from a transcript
+/// over all proofs and checks them against one vk with a single pairing.
+/// `false` only says that at least one proof is invalid, callers verify one by one to find it.
+pub fn verify_aggregates(
+    vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>,
+    proofs: &[Proof<Bn256, PlonkCsWidth4WithNextStepParams>],
+    aggregates: &[Aggregate],
+) -> Result<bool, ZkpError> {
+    let mut transcript = RollingKeccakTranscript::<Fr>::new();
+    for proof in proofs {
+        let mut proof_bytes = Vec::new();
+        proof.write(&mut proof_bytes)?;
+        transcript.commit_bytes(proof_bytes.as_slice());
+    }
+
+    let mut with_generator = G1::zero();
+    let mut with_x = G1::zero();
+    for [pair_with_generator, pair_with_x] in aggregates {
+        let r: Fr = transcript.get_challenge();
+        with_generator.add_assign(&pair_with_generator.mul(r.into_repr()));
+        with_x.add_assign(&pair_with_x.mul(r.into_repr()));
+    }
+    let pairing = Bn256::final_exponentiation(&Bn256::miller_loop(&[
+        (&with_generator.into_affine().prepare(), &vk.g2_elements[0].prepare()),
+        (&with_x.into_affine().prepare(), &vk.g2_elements[1].prepare()),
+    ]));
+    Ok(pairing == Some(<Bn256 as Engine>::Fqk::one()))
+}
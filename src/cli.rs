@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use crate::errors::ZkpError;
+use crate::info::R1csHeader;
+use crate::instance::{Helper, ProveRequest, SETUP_KEY_DIR, VerifyRequest, ZKPCircomInstance, ZKPFactory};
+use crate::pool::ProvingPool;
+use crate::srs::{self, SetupKeyCatalog, SrsManager};
+use crate::vk::{self, VkFormat};
+
+/// Proves and verifies circom circuits with PLONK, without arguments it serves the http api.
+#[derive(Parser, Debug)]
+#[command(name = "zkp")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Circuit sizes and the setup key the circuit needs
+    SetupInfo {
+        #[arg(long)]
+        r1cs: PathBuf,
+        #[command(flatten)]
+        setup: SetupArgs,
+    },
+    /// Writes the verification key of the circuit
+    Vk {
+        #[arg(long)]
+        r1cs: PathBuf,
+        /// bin, json or snarkjs
+        #[arg(long, default_value = "bin")]
+        format: VkFormat,
+        /// stdout when missing
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        setup: SetupArgs,
+    },
+    /// Writes the solidity verifier of the circuit
+    Sol {
+        #[arg(long)]
+        r1cs: PathBuf,
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        setup: SetupArgs,
+    },
+    /// Proves a .wtns file, or json inputs with the circuit's wasm, and prints the /prove response
+    Prove {
+        #[arg(long)]
+        r1cs: PathBuf,
+        #[arg(long, required_unless_present = "inputs", conflicts_with = "inputs")]
+        witness: Option<PathBuf>,
+        /// json file of named input signals, needs --wasm
+        #[arg(long, requires = "wasm")]
+        inputs: Option<PathBuf>,
+        #[arg(long)]
+        wasm: Option<PathBuf>,
+        /// also writes the raw proof bytes, the format `verify --proof` reads
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        setup: SetupArgs,
+    },
+    /// Verifies raw proof bytes against a vk file, exits with 1 when the proof is invalid
+    Verify {
+        /// vk as written by `zkp vk` or returned by /register
+        #[arg(long)]
+        vk: PathBuf,
+        #[arg(long)]
+        proof: PathBuf,
+        /// expected public inputs as a json array, the inputs_json of the prove output
+        #[arg(long)]
+        inputs: Option<PathBuf>,
+    },
+    /// Serves the http api
+    Serve,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SetupArgs {
+    /// directory of setup_2^N.key files
+    #[arg(long, env = "ZKP_SETUP_DIR", default_value = SETUP_KEY_DIR)]
+    pub setup_dir: PathBuf,
+}
+
+impl SetupArgs {
+    fn build(&self, r1cs: &Path) -> Result<ZKPCircomInstance, ZkpError> {
+        let catalog = SetupKeyCatalog::scan(&self.setup_dir)?;
+        // a single proof at a time, the pool only needs one thread
+        let factory = ZKPFactory::new(Arc::new(SrsManager::default()), Arc::new(catalog), Arc::new(ProvingPool::new(1)));
+        let key = r1cs.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        factory.build(key, fs::read(r1cs)?)
+    }
+}
+
+/// Runs every command but `serve`, returns whether it succeeded.
+pub fn run(command: Command) -> Result<bool, ZkpError> {
+    match command {
+        Command::SetupInfo { r1cs, setup } => {
+            let bytes = fs::read(&r1cs)?;
+            let header = R1csHeader::parse(bytes.as_slice())?;
+            let instance = setup.build(&r1cs)?;
+            let info = json!({
+                "constraints": instance.r1cs.constraints.len(),
+                "variables": instance.r1cs.num_variables,
+                "public_inputs": header.num_public_inputs,
+                "outputs": header.num_public_outputs,
+                "private_inputs": header.num_private_inputs,
+                "domain_size": instance.domain_size,
+                "required_power": srs::required_power(instance.domain_size),
+                "setup_power": instance.setup_power,
+            });
+            println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        }
+        Command::Vk { r1cs, format, out, setup } => {
+            let instance = setup.build(&r1cs)?;
+            let bytes = match format {
+                VkFormat::Bin => vk::to_bytes(&instance.vk)?,
+                VkFormat::Json => serde_json::to_vec_pretty(&vk::to_json(&instance.vk)).unwrap(),
+                VkFormat::Snarkjs => serde_json::to_vec_pretty(&vk::to_snarkjs(&instance.vk)?).unwrap(),
+            };
+            write_out(out, bytes.as_slice())?;
+        }
+        Command::Sol { r1cs, out, setup } => {
+            let (_, sol) = setup.build(&r1cs)?.get()?;
+            write_out(out, sol.as_slice())?;
+        }
+        Command::Prove { r1cs, witness, inputs, wasm, out, setup } => {
+            let mut instance = setup.build(&r1cs)?;
+            let wtns = match (witness, inputs, wasm) {
+                (Some(witness), _, _) => fs::read(witness)?,
+                (None, Some(inputs), Some(wasm)) => {
+                    instance = instance.with_witness_calculator(fs::read(wasm)?.as_slice())?;
+                    instance.calculate_witness(fs::read_to_string(inputs)?.as_str())?
+                }
+                _ => unreachable!("enforced by clap"),
+            };
+            let resp = instance.do_prove(ProveRequest { key: instance.key.clone(), wtns })?;
+            if let Some(out) = out {
+                fs::write(out, resp.proof.as_slice())?;
+            }
+            println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+        }
+        Command::Verify { vk, proof, inputs } => {
+            let vk = vk::from_bytes(fs::read(vk)?.as_slice())?;
+            let inputs = match inputs {
+                Some(inputs) => Some(serde_json::from_slice(fs::read(inputs)?.as_slice()).map_err(|e| {
+                    ZkpError::InvalidInputs(e.to_string())
+                })?),
+                None => None,
+            };
+            let req = VerifyRequest { key: String::new(), proof_bytes: fs::read(proof)?, inputs, serialized_proof: None };
+            let resp = crate::instance::verify_with_vk(&vk, &req)?;
+            println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+            return Ok(resp.verify);
+        }
+        Command::Serve => unreachable!("served by main"),
+    }
+    Ok(true)
+}
+
+fn write_out(out: Option<PathBuf>, bytes: &[u8]) -> Result<(), ZkpError> {
+    match out {
+        Some(path) => fs::write(path, bytes)?,
+        None => {
+            use std::io::Write;
+            std::io::stdout().write_all(bytes)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_cli_prove_and_verify() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let dir = std::env::temp_dir().join(format!("zkp-cli-{}", nanos));
+    fs::create_dir_all(&dir).unwrap();
+    let r1cs = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs"));
+    let witness = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns"));
+    let setup = || SetupArgs { setup_dir: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup")) };
+
+    let cli = Cli::try_parse_from(["zkp", "vk", "--r1cs", r1cs.to_str().unwrap(), "--out", dir.join("vk.bin").to_str().unwrap()]).expect("fail to parse");
+    assert!(run(cli.command.unwrap()).expect("fail to write vk"));
+    let prove = Command::Prove { r1cs, witness: Some(witness), inputs: None, wasm: None, out: Some(dir.join("proof.bin")), setup: setup() };
+    assert!(run(prove).expect("fail to prove"));
+    let verify = Command::Verify { vk: dir.join("vk.bin"), proof: dir.join("proof.bin"), inputs: None };
+    assert!(run(verify).expect("fail to verify"));
+
+    assert!(Cli::try_parse_from(["zkp", "prove", "--r1cs", "a.r1cs"]).is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
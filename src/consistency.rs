@@ -0,0 +1,140 @@
+//! Checks that `plonk::verify` and the solidity verifier rendered from `config/template.sol`
+//! take the same decision on every proof. Needs `solc` and `anvil` on the PATH, or a node in
+//! `ZKP_LOCAL_NODE`, run with `cargo test consistency -- --ignored`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use primitive_types::U256;
+use crate::anvil::LocalNode;
+use crate::contract_adapter::Deployer;
+use crate::errors::ZkpError;
+use crate::instance::{self, ProveRequest, VerifyRequest, ZKPCircomInstance, ZKPFactory};
+use crate::verifier_contract::OnChainVerifier;
+
+const CIRCOMS_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms");
+
+// the witness of mycircuit predates the `<stem>.wtns` naming
+const LEGACY_WITNESSES: [(&'static str, &'static str); 1] = [("mycircuit", "witness.wtns")];
+
+// index of a commitment coordinate and of an evaluation in the serialized proof layout
+const COMMITMENT_WORD: usize = 0;
+const EVALUATION_WORD: usize = 22;
+
+struct Case {
+    name: &'static str,
+    inputs: Vec<U256>,
+    serialized_proof: Vec<U256>,
+    expected: bool,
+}
+
+fn cases(inputs: Vec<U256>, serialized_proof: Vec<U256>) -> Vec<Case> {
+    let tamper = |word: usize| {
+        let mut tampered = serialized_proof.clone();
+        tampered[word] = tampered[word] + U256::from(1);
+        tampered
+    };
+    let mut cases = vec![
+        Case { name: "valid", inputs: inputs.clone(), serialized_proof: serialized_proof.clone(), expected: true },
+        Case { name: "tampered commitment", inputs: inputs.clone(), serialized_proof: tamper(COMMITMENT_WORD), expected: false },
+        Case { name: "tampered evaluation", inputs: inputs.clone(), serialized_proof: tamper(EVALUATION_WORD), expected: false },
+    ];
+    if !inputs.is_empty() {
+        let mut wrong_inputs = inputs.clone();
+        wrong_inputs[0] = wrong_inputs[0] + U256::from(1);
+        cases.push(Case { name: "wrong public inputs", inputs: wrong_inputs, serialized_proof: serialized_proof.clone(), expected: false });
+        cases.push(Case { name: "missing public inputs", inputs: vec![], serialized_proof, expected: false });
+    }
+    cases
+}
+
+/// Every r1cs of `testdata/circoms` with the witness next to it, as (name, r1cs, wtns) paths.
+/// The witness of `<stem>.r1cs` is `<stem>.wtns`, circuits without one are skipped.
+fn circuits() -> Vec<(String, PathBuf, PathBuf)> {
+    let mut circuits = Vec::new();
+    for entry in fs::read_dir(CIRCOMS_DIR).expect("fail to read testdata") {
+        let r1cs = entry.expect("fail to read testdata").path();
+        if r1cs.extension().map_or(true, |ext| ext != "r1cs") {
+            continue;
+        }
+        let name = r1cs.file_stem().unwrap().to_string_lossy().to_string();
+        let wtns = match LEGACY_WITNESSES.iter().find(|(stem, _)| *stem == name) {
+            Some((_, wtns)) => r1cs.with_file_name(wtns),
+            None => r1cs.with_extension("wtns"),
+        };
+        if !wtns.exists() {
+            println!("skip circuit:{}, no witness at {}", name, wtns.display());
+            continue;
+        }
+        circuits.push((name, r1cs, wtns));
+    }
+    circuits.sort();
+    circuits
+}
+
+/// A proof that fails to decode is a rejection, same as the contract reverting on it.
+fn off_chain(instance: &ZKPCircomInstance, case: &Case) -> bool {
+    let req = VerifyRequest { key: instance.key.clone(), proof_bytes: vec![], inputs: Some(case.inputs.clone()), serialized_proof: Some(case.serialized_proof.clone()) };
+    match instance::verify_with_vk(&instance.vk, &req) {
+        Ok(resp) => resp.verify,
+        Err(ZkpError::InvalidProof(_)) | Err(ZkpError::InvalidInputs(_)) => false,
+        Err(e) => panic!("case {}: {}", case.name, e),
+    }
+}
+
+fn build(name: &str, r1cs: &Path) -> Option<ZKPCircomInstance> {
+    let r1cs = fs::read(r1cs).expect("fail to read r1cs");
+    match ZKPFactory::default().build(name.to_string(), r1cs) {
+        Ok(instance) => Some(instance),
+        // bigger setup keys come from testdata/prepare.sh
+        Err(e @ ZkpError::SetupTooSmall { .. }) => {
+            println!("skip circuit:{}, {}", name, e);
+            None
+        }
+        Err(e) => panic!("fail to build {}: {}", name, e),
+    }
+}
+
+#[test]
+pub fn test_circuits() {
+    let circuits: Vec<(String, String)> = circuits().into_iter()
+        .map(|(name, _, wtns)| (name, wtns.file_name().unwrap().to_string_lossy().to_string()))
+        .collect();
+    assert!(circuits.contains(&(String::from("mycircuit"), String::from("witness.wtns"))));
+    assert!(circuits.contains(&(String::from("single_tx"), String::from("single_tx.wtns"))));
+}
+
+#[test]
+#[ignore = "needs anvil and solc"]
+pub fn test_off_chain_and_on_chain_agree() {
+    let node = LocalNode::for_tests();
+    let deployer = Deployer::new(node.deployer_config().expect("no funded wallet")).expect("fail to build deployer");
+    let verifier: OnChainVerifier = deployer.on_chain_verifier();
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+
+    let mut checked = 0;
+    for (name, r1cs, wtns) in circuits() {
+        let name = name.as_str();
+        let instance = match build(name, r1cs.as_path()) {
+            Some(instance) => instance,
+            None => continue,
+        };
+        let (_, sol) = instance.get().expect("fail to render verifier");
+        let deployment = rt.block_on(deployer.deploy(sol)).expect("fail to deploy");
+
+        let wtns = fs::read(wtns).expect("fail to read witness");
+        let res = instance.do_prove(ProveRequest { key: name.to_string(), wtns }).expect("fail to prove");
+        let serialized_proof: Vec<U256> = serde_json::from_str(res.json_proof.as_str()).unwrap();
+
+        for case in cases(res.inputs, serialized_proof) {
+            let on_chain = rt.block_on(verifier.verify(deployment.address, case.inputs.as_slice(), case.serialized_proof.as_slice(), false))
+                .expect("fail to call verifier")
+                .verify;
+            let off_chain = off_chain(&instance, &case);
+            println!("circuit:{} case:{} off-chain:{} on-chain:{}", name, case.name, off_chain, on_chain);
+            assert_eq!(off_chain, on_chain, "circuit {} case {}", name, case.name);
+            assert_eq!(off_chain, case.expected, "circuit {} case {}", name, case.name);
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
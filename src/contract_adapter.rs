@@ -1,74 +1,183 @@
-use std::path::PathBuf;
+use std::fs;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use ethers::abi::Abi;
-use ethers::contract::{ContractError, ContractFactory, ContractInstance};
-use ethers::core::k256::ecdsa::SigningKey;
+use ethers::contract::ContractFactory;
 use ethers::middleware::SignerMiddleware;
-use ethers::prelude::TransactionReceipt;
 use ethers::providers::{Http, Middleware, Provider};
-use ethers::signers::{LocalWallet, Signer, Wallet};
+use ethers::signers::{LocalWallet, Signer};
 use ethers::solc::{Artifact, Project, ProjectPathsConfig};
-use ethers::types::Bytes;
-use ethers_core::k256::elliptic_curve::weierstrass::add;
+use ethers::types::{Address, Bytes, H256};
+use serde::{Serialize, Deserialize};
+use crate::errors::ZkpError;
+use crate::verifier_contract::OnChainVerifier;
 
-pub struct SimpleDeployer {}
+/// Contract of the rendered `config/template.sol` that gets deployed, the others are its libraries.
+pub const VERIFIER_CONTRACT: &'static str = "KeyedVerifier";
+const DEFAULT_RPC_URL: &'static str = "http://127.0.0.1:8545";
+const DEFAULT_CONFIRMATIONS: usize = 1;
 
-impl Default for SimpleDeployer {
-    fn default() -> Self {
-        SimpleDeployer {}
+/// Where and as whom verifiers are deployed, read from the environment by `from_env`.
+#[derive(Debug, Clone)]
+pub struct DeployerConfig {
+    pub rpc_url: String,
+    /// asked from the node when missing
+    pub chain_id: Option<u64>,
+    /// hex private key of the deploying account
+    pub private_key: String,
+    pub confirmations: usize,
+}
+
+impl DeployerConfig {
+    pub fn new(rpc_url: impl Into<String>, private_key: impl Into<String>) -> Self {
+        Self { rpc_url: rpc_url.into(), chain_id: None, private_key: private_key.into(), confirmations: DEFAULT_CONFIRMATIONS }
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    /// `ZKP_DEPLOYER_KEY` is the signer, deploying is disabled without it.
+    /// `ZKP_RPC_URL`, `ZKP_CHAIN_ID` and `ZKP_DEPLOY_CONFIRMATIONS` are optional.
+    pub fn from_env() -> Result<Option<Self>, ZkpError> {
+        let private_key = match std::env::var("ZKP_DEPLOYER_KEY") {
+            Ok(key) => key,
+            Err(_) => return Ok(None),
+        };
+        let mut config = Self::new(rpc_url_from_env(), private_key);
+        if let Ok(chain_id) = std::env::var("ZKP_CHAIN_ID") {
+            config.chain_id = Some(chain_id.parse::<u64>().map_err(|e| {
+                ZkpError::InvalidDeployerConfig(format!("ZKP_CHAIN_ID: {}", e))
+            })?);
+        }
+        if let Ok(confirmations) = std::env::var("ZKP_DEPLOY_CONFIRMATIONS") {
+            config.confirmations = confirmations.parse::<usize>().map_err(|e| {
+                ZkpError::InvalidDeployerConfig(format!("ZKP_DEPLOY_CONFIRMATIONS: {}", e))
+            })?;
+        }
+        Ok(Some(config))
     }
 }
 
-impl SimpleDeployer {
-    pub async fn deploy(self, name: &str) -> (SimpleDeployer, ContractInstance<Arc<SignerMiddleware<ethers::providers::Provider<Http>, Wallet<ethers_core::k256::ecdsa::SigningKey>>>, SignerMiddleware<ethers::providers::Provider<Http>, Wallet<ethers_core::k256::ecdsa::SigningKey>>>, TransactionReceipt) {
-        let (abi, bytecode, c) = get_contract("KeyedVerifier");
-        let abi_code = abi.unwrap();
-        let key = "b7700998b973a2cae0cb8e8a328171399c043e57289735aca5f2419bd622297a";
-        let wallet = key.parse::<LocalWallet>().unwrap();
-        let address = wallet.address();
-
-        let provider = Provider::<Http>::try_from("http://127.0.0.1:26659")
-            .unwrap();
-        let client = SignerMiddleware::new(provider, (wallet as Wallet<ethers_core::k256::ecdsa::SigningKey>).with_chain_id(100 as u64));
-        let client = Arc::new(client);
-
-        let factory = ContractFactory::new(abi_code.clone(), bytecode.unwrap(), client.clone());
-        let deployer = factory.deploy(()).expect("f");
-
-
-        let pending_tx = deployer
-            .client()
-            .send_transaction(deployer.tx.clone(), None)
-            .await.expect("fff");
-        let receipt = pending_tx
-            .confirmations(1)
-            .await.expect("fail")
-            .expect("fail2");
-        let address = receipt.contract_address.unwrap();
-        let contract: ContractInstance<Arc<SignerMiddleware<ethers::providers::Provider<Http>, Wallet<ethers_core::k256::ecdsa::SigningKey>>>, SignerMiddleware<ethers::providers::Provider<Http>, Wallet<ethers_core::k256::ecdsa::SigningKey>>> = ContractInstance::new(address, abi_code.clone(), client.clone());
-
-        println!("合约:{} 部署成功,合约地址:{}", name, address.to_string());
-
-        (self, contract, receipt)
+/// `ZKP_RPC_URL`, the node deployments and on-chain verification go to.
+pub fn rpc_url_from_env() -> String {
+    std::env::var("ZKP_RPC_URL").unwrap_or(String::from(DEFAULT_RPC_URL))
+}
+
+/// A verifier contract deployed for a circuit version, kept in its `CircuitMeta`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Deployment {
+    pub address: Address,
+    pub chain_id: u64,
+    pub tx_hash: H256,
+    pub block_number: Option<u64>,
+    pub deployed_at: u64,
+}
+
+/// Compiles rendered solidity verifiers and deploys them with the configured signer.
+pub struct Deployer {
+    provider: Provider<Http>,
+    wallet: LocalWallet,
+    chain_id: Option<u64>,
+    confirmations: usize,
+}
+
+impl Deployer {
+    pub fn new(config: DeployerConfig) -> Result<Self, ZkpError> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str()).map_err(|e| {
+            ZkpError::InvalidDeployerConfig(format!("rpc url {}: {}", config.rpc_url, e))
+        })?;
+        let wallet = config.private_key.trim().trim_start_matches("0x").parse::<LocalWallet>().map_err(|e| {
+            ZkpError::InvalidDeployerConfig(format!("private key: {}", e))
+        })?;
+        Ok(Self { provider, wallet, chain_id: config.chain_id, confirmations: config.confirmations })
+    }
+
+    /// Address the contracts are deployed from.
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Verifies against deployed contracts through the same node, sending transactions as the deployer.
+    pub fn on_chain_verifier(&self) -> OnChainVerifier {
+        OnChainVerifier::with_signer(self.provider.clone(), self.wallet.clone(), self.chain_id)
+    }
+
+    /// Compiles `sol` and deploys its `KeyedVerifier`, waiting for the configured confirmations.
+    pub async fn deploy(&self, sol: Vec<u8>) -> Result<Deployment, ZkpError> {
+        // solc runs as a child process, keep it off the async workers
+        let (abi, bytecode) = tokio::task::spawn_blocking(move || compile(sol.as_slice(), VERIFIER_CONTRACT)).await.map_err(|e| {
+            ZkpError::WorkerUnavailable(e.to_string())
+        })??;
+        let chain_id = match self.chain_id {
+            Some(chain_id) => chain_id,
+            None => self.provider.get_chainid().await.map_err(|e| {
+                ZkpError::DeployFailed(e.to_string())
+            })?.as_u64(),
+        };
+        let client = Arc::new(SignerMiddleware::new(self.provider.clone(), self.wallet.clone().with_chain_id(chain_id)));
+
+        let factory = ContractFactory::new(abi, bytecode, client.clone());
+        let deployer = factory.deploy(()).map_err(|e| {
+            ZkpError::DeployFailed(e.to_string())
+        })?;
+        let pending_tx = client.send_transaction(deployer.tx.clone(), None).await.map_err(|e| {
+            ZkpError::DeployFailed(e.to_string())
+        })?;
+        let receipt = pending_tx.confirmations(self.confirmations).await.map_err(|e| {
+            ZkpError::DeployFailed(e.to_string())
+        })?.ok_or_else(|| ZkpError::DeployFailed(String::from("deploy transaction was dropped")))?;
+        let address = receipt.contract_address.ok_or_else(|| {
+            ZkpError::DeployFailed(format!("transaction {:?} did not create a contract", receipt.transaction_hash))
+        })?;
+        let deployed_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        Ok(Deployment { address, chain_id, tx_hash: receipt.transaction_hash, block_number: receipt.block_number.map(|n| n.as_u64()), deployed_at })
     }
 }
 
-pub fn get_contract(name: &str) -> (Option<Abi>, Option<Bytes>, Option<Bytes>) {
-    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("config/contracts");
-    println!("{:?}", root);
-    let paths = ProjectPathsConfig::builder().root(&root).sources(&root).build().unwrap();
-    println!("{:?}", paths);
-    let project = Project::builder().paths(paths).ephemeral().no_artifacts().build().unwrap();
-    let output = project.compile().unwrap();
-    let contract = output.find_first(name).expect("could not find contract").clone();
-    contract.into_parts()
+/// Compiles a single solidity source with the local solc and returns the abi and bytecode of contract `name`.
+pub fn compile(sol: &[u8], name: &str) -> Result<(Abi, Bytes), ZkpError> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let root = std::env::temp_dir().join(format!("zkp-solc-{}", nanos));
+    fs::create_dir_all(&root)?;
+    fs::write(root.join(format!("{}.sol", name)), sol)?;
+    let compiled = compile_dir(&root, name);
+    fs::remove_dir_all(&root)?;
+    compiled
 }
 
+fn compile_dir(root: &std::path::Path, name: &str) -> Result<(Abi, Bytes), ZkpError> {
+    let paths = ProjectPathsConfig::builder().root(root).sources(root).build().map_err(|e| {
+        ZkpError::CompilationFailed(e.to_string())
+    })?;
+    let project = Project::builder().paths(paths).ephemeral().no_artifacts().build().map_err(|e| {
+        ZkpError::CompilationFailed(e.to_string())
+    })?;
+    let output = project.compile().map_err(|e| {
+        ZkpError::CompilationFailed(e.to_string())
+    })?;
+    if output.has_compiler_errors() {
+        return Err(ZkpError::CompilationFailed(output.to_string()));
+    }
+    let contract = output.find_first(name).ok_or_else(|| {
+        ZkpError::CompilationFailed(format!("contract {} not found", name))
+    })?.clone();
+    let (abi, bytecode, _) = contract.into_parts();
+    match (abi, bytecode) {
+        (Some(abi), Some(bytecode)) => Ok((abi, bytecode)),
+        _ => Err(ZkpError::CompilationFailed(format!("contract {} has no abi or bytecode", name))),
+    }
+}
 
 #[test]
-pub fn test_deploy() {
-    let dep = SimpleDeployer::default();
-    tokio::runtime::Builder::new_current_thread().enable_time().enable_io().build().unwrap().block_on(async {
-        dep.deploy("KeyedVerifier").await;
-    });
-}
\ No newline at end of file
+pub fn test_deployer_config() {
+    // anvil's first default account
+    let key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    let deployer = Deployer::new(DeployerConfig::new(DEFAULT_RPC_URL, format!("0x{}", key)).with_chain_id(31337)).expect("fail to build deployer");
+    assert_eq!(deployer.address(), "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266".parse::<Address>().unwrap());
+    assert_eq!(deployer.chain_id, Some(31337));
+
+    assert!(matches!(Deployer::new(DeployerConfig::new(DEFAULT_RPC_URL, "not a key")), Err(ZkpError::InvalidDeployerConfig(_))));
+    assert!(matches!(Deployer::new(DeployerConfig::new("not a url", key)), Err(ZkpError::InvalidDeployerConfig(_))));
+}
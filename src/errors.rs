@@ -0,0 +1,127 @@
+use std::fmt::{Display, Formatter};
+use std::{error, io};
+
+#[derive(Debug)]
+pub enum ZkpError {
+    UnknownCircuit(String),
+    CircuitExists(String),
+    InvalidCircuitKey(String),
+    InvalidR1cs(String),
+    InvalidWitness(String),
+    WitnessSizeMismatch { expected: usize, actual: usize },
+    UnsatisfiedConstraint { index: usize },
+    InvalidProof(String),
+    InvalidVerificationKey(String),
+    InvalidInputs(String),
+    SetupTooSmall { required_power: u32, largest_power: Option<u32> },
+    ProvingFailed(String),
+    ProofSelfCheckFailed,
+    WorkerUnavailable(String),
+    UnknownJob(String),
+    JobNotCancellable(String),
+    InvalidWasm(String),
+    NoWitnessCalculator(String),
+    WitnessCalculationFailed(String),
+    DeployerNotConfigured,
+    InvalidDeployerConfig(String),
+    CompilationFailed(String),
+    DeployFailed(String),
+    NotDeployed(String),
+    ChainRequestFailed(String),
+    InvalidEventAbi(String),
+    UnknownSubscription(String),
+    SubscriptionExists(String),
+    EventHandlerFailed(String),
+    Io(io::Error),
+}
+
+impl ZkpError {
+    /// Stable machine readable identifier, used as `error.code` in http responses.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ZkpError::UnknownCircuit(_) => "unknown_circuit",
+            ZkpError::CircuitExists(_) => "circuit_exists",
+            ZkpError::InvalidCircuitKey(_) => "invalid_circuit_key",
+            ZkpError::InvalidR1cs(_) => "invalid_r1cs",
+            ZkpError::InvalidWitness(_) => "invalid_witness",
+            ZkpError::WitnessSizeMismatch { .. } => "witness_size_mismatch",
+            ZkpError::UnsatisfiedConstraint { .. } => "unsatisfied_constraint",
+            ZkpError::InvalidProof(_) => "invalid_proof",
+            ZkpError::InvalidVerificationKey(_) => "invalid_verification_key",
+            ZkpError::InvalidInputs(_) => "invalid_inputs",
+            ZkpError::SetupTooSmall { .. } => "setup_too_small",
+            ZkpError::ProvingFailed(_) => "proving_failed",
+            ZkpError::ProofSelfCheckFailed => "proof_self_check_failed",
+            ZkpError::WorkerUnavailable(_) => "worker_unavailable",
+            ZkpError::UnknownJob(_) => "unknown_job",
+            ZkpError::JobNotCancellable(_) => "job_not_cancellable",
+            ZkpError::InvalidWasm(_) => "invalid_wasm",
+            ZkpError::NoWitnessCalculator(_) => "no_witness_calculator",
+            ZkpError::WitnessCalculationFailed(_) => "witness_calculation_failed",
+            ZkpError::DeployerNotConfigured => "deployer_not_configured",
+            ZkpError::InvalidDeployerConfig(_) => "invalid_deployer_config",
+            ZkpError::CompilationFailed(_) => "compilation_failed",
+            ZkpError::DeployFailed(_) => "deploy_failed",
+            ZkpError::NotDeployed(_) => "not_deployed",
+            ZkpError::ChainRequestFailed(_) => "chain_request_failed",
+            ZkpError::InvalidEventAbi(_) => "invalid_event_abi",
+            ZkpError::UnknownSubscription(_) => "unknown_subscription",
+            ZkpError::SubscriptionExists(_) => "subscription_exists",
+            ZkpError::EventHandlerFailed(_) => "event_handler_failed",
+            ZkpError::Io(_) => "io_error",
+        }
+    }
+}
+
+impl Display for ZkpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZkpError::UnknownCircuit(key) => write!(f, "circuit {} is not registered", key),
+            ZkpError::CircuitExists(key) => write!(f, "circuit {} is already registered with a different r1cs", key),
+            ZkpError::InvalidCircuitKey(key) => write!(f, "circuit key {:?} must be 1 to {} bytes without '@' or control characters", key, crate::store::MAX_KEY_LEN),
+            ZkpError::InvalidR1cs(e) => write!(f, "invalid r1cs: {}", e),
+            ZkpError::InvalidWitness(e) => write!(f, "invalid witness: {}", e),
+            ZkpError::WitnessSizeMismatch { expected, actual } => write!(f, "witness has {} values, circuit expects {}", actual, expected),
+            ZkpError::UnsatisfiedConstraint { index } => write!(f, "witness does not satisfy constraint {}", index),
+            ZkpError::InvalidProof(e) => write!(f, "invalid proof: {}", e),
+            ZkpError::InvalidVerificationKey(e) => write!(f, "invalid verification key: {}", e),
+            ZkpError::InvalidInputs(e) => write!(f, "invalid public inputs: {}", e),
+            ZkpError::SetupTooSmall { required_power, largest_power: Some(largest) } => write!(f, "circuit requires a setup key of power {}, largest available is 2^{}", required_power, largest),
+            ZkpError::SetupTooSmall { required_power, largest_power: None } => write!(f, "circuit requires a setup key of power {}, no setup key is available", required_power),
+            ZkpError::ProvingFailed(e) => write!(f, "proving failed: {}", e),
+            ZkpError::ProofSelfCheckFailed => write!(f, "generated proof failed verification"),
+            ZkpError::WorkerUnavailable(e) => write!(f, "circuit worker unavailable: {}", e),
+            ZkpError::UnknownJob(id) => write!(f, "job {} does not exist", id),
+            ZkpError::JobNotCancellable(id) => write!(f, "job {} is already running", id),
+            ZkpError::InvalidWasm(e) => write!(f, "invalid witness generator: {}", e),
+            ZkpError::NoWitnessCalculator(key) => write!(f, "circuit {} was registered without a witness generator", key),
+            ZkpError::WitnessCalculationFailed(e) => write!(f, "witness calculation failed: {}", e),
+            ZkpError::DeployerNotConfigured => write!(f, "no deployer is configured, set ZKP_DEPLOYER_KEY or ZKP_LOCAL_NODE"),
+            ZkpError::InvalidDeployerConfig(e) => write!(f, "invalid deployer config: {}", e),
+            ZkpError::CompilationFailed(e) => write!(f, "solidity compilation failed: {}", e),
+            ZkpError::DeployFailed(e) => write!(f, "deploy failed: {}", e),
+            ZkpError::NotDeployed(key) => write!(f, "circuit {} has no deployed verifier", key),
+            ZkpError::ChainRequestFailed(e) => write!(f, "chain request failed: {}", e),
+            ZkpError::InvalidEventAbi(e) => write!(f, "invalid event abi: {}", e),
+            ZkpError::UnknownSubscription(name) => write!(f, "event subscription {} does not exist", name),
+            ZkpError::SubscriptionExists(name) => write!(f, "event subscription {} already exists", name),
+            ZkpError::EventHandlerFailed(e) => write!(f, "event handler failed: {}", e),
+            ZkpError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl error::Error for ZkpError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ZkpError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ZkpError {
+    fn from(e: io::Error) -> Self {
+        ZkpError::Io(e)
+    }
+}
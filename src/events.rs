@@ -1,47 +1,440 @@
-use std::sync::Arc;
-use std::thread;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use ethers::abi::AbiDecode;
-use ethers::prelude::{Address, Filter, H160, H256, ProviderError};
-use ethers::providers::{Middleware, Provider, StreamExt, Ws};
-use ethers::solc::utils::RuntimeOrHandle::Runtime;
-use ethers::types::U256;
-use eyre::Result;
-
-pub async fn listen_deposit(address: &str) -> Result<(), ProviderError> {
-    let client =
-        Provider::<Ws>::connect("ws://127.0.0.1:8546")
-            .await?;
-    let client = Arc::new(client);
-    let erc20_transfer_filter =
-        Filter::new()
-            .from_block(1)
-            .address(address.parse::<Address>().expect("failed"))
-            .event("Transfer(address,address,uint256)");
-
-    let mut stream = client.subscribe_logs(&erc20_transfer_filter).await?.take(2);
-
-    while let Some(log) = stream.next().await {
-        println!(
-            "block: {:?}, tx: {:?}, token: {:?}, from: {:?}, to: {:?}, amount: {:?}",
-            log.block_number,
-            log.transaction_hash,
-            log.address,
-            Address::from(log.topics[1]),
-            Address::from(log.topics[2]),
-            U256::decode(log.data)
-        )
-    }
-    Ok(())
+use ethers::abi::{parse_abi, Abi, Event, LogParam, RawLog};
+use ethers::contract::{EthEvent, LogMeta};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Filter, Log, ValueOrArray, H256};
+use serde::{Serialize, Deserialize};
+use tokio::sync::oneshot;
+use crate::errors::ZkpError;
+
+// processed blocks remembered per subscription to find where a reorg forked
+const REORG_WINDOW: usize = 64;
+
+/// Logs of some events, emitted by any of `addresses` or by any contract when it is empty.
+/// Anonymous events have no signature topic and are never matched.
+#[derive(Debug, Clone)]
+pub struct EventSubscription {
+    pub name: String,
+    pub addresses: Vec<Address>,
+    pub events: Vec<Event>,
+}
+
+impl EventSubscription {
+    /// `signatures` are human readable, e.g. `event Transfer(address indexed from, address indexed to, uint256 value)`.
+    pub fn from_signatures(name: impl Into<String>, addresses: Vec<Address>, signatures: &[&str]) -> Result<Self, ZkpError> {
+        let abi = parse_abi(signatures).map_err(|e| {
+            ZkpError::InvalidEventAbi(e.to_string())
+        })?;
+        Self::new(name.into(), addresses, abi)
+    }
+
+    /// Every event of a json abi, e.g. the `.abi` files next to the contracts.
+    pub fn from_abi(name: impl Into<String>, addresses: Vec<Address>, abi_json: &str) -> Result<Self, ZkpError> {
+        let abi: Abi = serde_json::from_str(abi_json).map_err(|e| {
+            ZkpError::InvalidEventAbi(e.to_string())
+        })?;
+        Self::new(name.into(), addresses, abi)
+    }
+
+    fn new(name: String, addresses: Vec<Address>, abi: Abi) -> Result<Self, ZkpError> {
+        let events: Vec<Event> = abi.events().filter(|event| !event.anonymous).cloned().collect();
+        if events.is_empty() {
+            return Err(ZkpError::InvalidEventAbi(format!("subscription {} has no named event", name)));
+        }
+        Ok(Self { name, addresses, events })
+    }
+
+    fn filter(&self) -> Filter {
+        let topics = self.events.iter().map(|event| Some(event.signature())).collect();
+        let filter = Filter::new().topic0(ValueOrArray::Array(topics));
+        if self.addresses.is_empty() {
+            return filter;
+        }
+        filter.address(ValueOrArray::Array(self.addresses.clone()))
+    }
+
+    /// `None` for logs of events the subscription doesn't know.
+    fn decode(&self, log: Log) -> Result<Option<DecodedEvent>, ZkpError> {
+        let event = match self.events.iter().find(|event| log.topics.first() == Some(&event.signature())) {
+            Some(event) => event,
+            None => return Ok(None),
+        };
+        let parsed = event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() }).map_err(|e| {
+            ZkpError::InvalidEventAbi(format!("{} log in tx {:?}: {}", event.name, log.transaction_hash, e))
+        })?;
+        Ok(Some(DecodedEvent { subscription: self.name.clone(), event: event.name.clone(), params: parsed.params, log }))
+    }
 }
 
+/// A log decoded against the abi of its subscription.
+#[derive(Debug, Clone)]
+pub struct DecodedEvent {
+    pub subscription: String,
+    pub event: String,
+    pub params: Vec<LogParam>,
+    pub log: Log,
+}
+
+/// Receives the confirmed events of the subscription it was registered for, in chain order.
+/// Delivery is at least once: a failed handler or a reorg deeper than the confirmations replays events.
+pub trait EventHandler: Send + Sync {
+    fn handle(&self, event: &DecodedEvent) -> Result<(), ZkpError>;
+}
+
+impl<F> EventHandler for F where F: Fn(&DecodedEvent) -> Result<(), ZkpError> + Send + Sync {
+    fn handle(&self, event: &DecodedEvent) -> Result<(), ZkpError> {
+        self(event)
+    }
+}
+
+/// Decodes into an `EthEvent` struct, events of other signatures are skipped. Built by `typed`.
+pub struct TypedHandler<E, F> {
+    f: F,
+    _event: PhantomData<fn() -> E>,
+}
+
+/// Handler taking the event as `E`, e.g. a struct deriving `EthEvent` or generated by `abigen!`.
+pub fn typed<E, F>(f: F) -> TypedHandler<E, F> where E: EthEvent, F: Fn(E, &LogMeta) -> Result<(), ZkpError> + Send + Sync {
+    TypedHandler { f, _event: PhantomData }
+}
+
+impl<E, F> EventHandler for TypedHandler<E, F> where E: EthEvent, F: Fn(E, &LogMeta) -> Result<(), ZkpError> + Send + Sync {
+    fn handle(&self, event: &DecodedEvent) -> Result<(), ZkpError> {
+        if event.log.topics.first() != Some(&E::signature()) {
+            return Ok(());
+        }
+        let typed = E::decode_log(&RawLog { topics: event.log.topics.clone(), data: event.log.data.to_vec() }).map_err(|e| {
+            ZkpError::InvalidEventAbi(format!("{}: {}", E::name(), e))
+        })?;
+        (self.f)(typed, &LogMeta::from(&event.log))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: H256,
+}
+
+/// Blocks a subscription has processed up to, newest last.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    pub blocks: Vec<BlockRef>,
+}
+
+impl Checkpoint {
+    pub fn last_block(&self) -> Option<u64> {
+        self.blocks.last().map(|b| b.number)
+    }
+}
+
+/// Persists how far every subscription got, so a restart resumes instead of replaying the chain.
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self, subscription: &str) -> Result<Option<Checkpoint>, Error>;
+    fn save(&self, subscription: &str, checkpoint: &Checkpoint) -> Result<(), Error>;
+}
+
+#[derive(Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: RwLock<HashMap<String, Checkpoint>>,
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn load(&self, subscription: &str) -> Result<Option<Checkpoint>, Error> {
+        Ok(self.checkpoints.read().unwrap().get(subscription).cloned())
+    }
+
+    fn save(&self, subscription: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+        self.checkpoints.write().unwrap().insert(subscription.to_string(), checkpoint.clone());
+        Ok(())
+    }
+}
+
+/// Every subscription's checkpoint in one json file, replaced atomically on save.
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    checkpoints: RwLock<HashMap<String, Checkpoint>>,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let checkpoints = if path.exists() {
+            serde_json::from_slice(fs::read(&path)?.as_slice()).map_err(|e| {
+                Error::new(ErrorKind::InvalidData, e)
+            })?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self { path, checkpoints: RwLock::new(checkpoints) })
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn load(&self, subscription: &str) -> Result<Option<Checkpoint>, Error> {
+        Ok(self.checkpoints.read().unwrap().get(subscription).cloned())
+    }
+
+    fn save(&self, subscription: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let mut checkpoints = self.checkpoints.write().unwrap();
+        checkpoints.insert(subscription.to_string(), checkpoint.clone());
+        let bytes = serde_json::to_vec_pretty(&*checkpoints).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, e)
+        })?;
+        // a crash mid write must not lose every checkpoint
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventServiceConfig {
+    /// blocks on top of a log's block before it is dispatched
+    pub confirmations: u64,
+    pub poll_interval: Duration,
+    /// where subscriptions without a checkpoint start
+    pub start_block: u64,
+    /// upper bound of the block range of a single `eth_getLogs`
+    pub max_block_range: u64,
+}
+
+impl Default for EventServiceConfig {
+    fn default() -> Self {
+        Self { confirmations: 12, poll_interval: Duration::from_secs(5), start_block: 0, max_block_range: 1000 }
+    }
+}
+
+/// Polls the node for the logs of every subscription and hands the confirmed ones to its handlers.
+pub struct EventService<M> {
+    client: Arc<M>,
+    config: EventServiceConfig,
+    checkpoints: Arc<dyn CheckpointStore>,
+    subscriptions: Vec<(EventSubscription, Vec<Arc<dyn EventHandler>>)>,
+}
+
+impl<M: Middleware> EventService<M> {
+    pub fn new(client: Arc<M>, config: EventServiceConfig, checkpoints: Arc<dyn CheckpointStore>) -> Self {
+        Self { client, config, checkpoints, subscriptions: Vec::new() }
+    }
+
+    pub fn subscribe(&mut self, subscription: EventSubscription) -> Result<(), ZkpError> {
+        if self.subscriptions.iter().any(|(s, _)| s.name == subscription.name) {
+            return Err(ZkpError::SubscriptionExists(subscription.name));
+        }
+        self.subscriptions.push((subscription, Vec::new()));
+        Ok(())
+    }
+
+    /// Registers `handler` for the events of subscription `name`, handlers run in registration order.
+    pub fn on(&mut self, name: &str, handler: impl EventHandler + 'static) -> Result<(), ZkpError> {
+        let (_, handlers) = self.subscriptions.iter_mut().find(|(s, _)| s.name == name).ok_or_else(|| {
+            ZkpError::UnknownSubscription(name.to_string())
+        })?;
+        handlers.push(Arc::new(handler));
+        Ok(())
+    }
+
+    /// Dispatches everything confirmed since the last poll, returns how many events were handled.
+    pub async fn poll(&self) -> Result<usize, ZkpError> {
+        let latest = self.client.get_block_number().await.map_err(|e| {
+            ZkpError::ChainRequestFailed(e.to_string())
+        })?.as_u64();
+        let safe = match latest.checked_sub(self.config.confirmations) {
+            Some(safe) => safe,
+            None => return Ok(0),
+        };
+        let mut handled = 0;
+        for (subscription, handlers) in self.subscriptions.iter() {
+            handled += self.poll_one(subscription, handlers.as_slice(), safe).await?;
+        }
+        Ok(handled)
+    }
+
+    async fn poll_one(&self, subscription: &EventSubscription, handlers: &[Arc<dyn EventHandler>], safe: u64) -> Result<usize, ZkpError> {
+        let mut checkpoint = self.checkpoints.load(subscription.name.as_str())?.unwrap_or_default();
+        self.rewind_reorged(subscription, &mut checkpoint).await?;
+        let mut from = checkpoint.last_block().map(|b| b + 1).unwrap_or(self.config.start_block);
+        let mut handled = 0;
+        while from <= safe {
+            let to = safe.min(from + self.config.max_block_range.max(1) - 1);
+            let filter = subscription.filter().from_block(from).to_block(to);
+            let mut logs = self.client.get_logs(&filter).await.map_err(|e| {
+                ZkpError::ChainRequestFailed(e.to_string())
+            })?;
+            logs.sort_by_key(|log| (log.block_number, log.log_index));
+            for log in logs {
+                let event = match subscription.decode(log)? {
+                    Some(event) => event,
+                    None => continue,
+                };
+                for handler in handlers.iter() {
+                    handler.handle(&event).map_err(|e| {
+                        // the checkpoint stays before this range, it is retried on the next poll
+                        ZkpError::EventHandlerFailed(format!("{} {}: {}", event.subscription, event.event, e))
+                    })?;
+                }
+                handled += 1;
+            }
+            let hash = self.block_hash(to).await?.ok_or_else(|| ZkpError::ChainRequestFailed(format!("block {} not found", to)))?;
+            checkpoint.blocks.push(BlockRef { number: to, hash });
+            if checkpoint.blocks.len() > REORG_WINDOW {
+                checkpoint.blocks.remove(0);
+            }
+            self.checkpoints.save(subscription.name.as_str(), &checkpoint)?;
+            from = to + 1;
+        }
+        Ok(handled)
+    }
+
+    /// Drops processed blocks the chain no longer has, so their range is dispatched again from the new fork.
+    async fn rewind_reorged(&self, subscription: &EventSubscription, checkpoint: &mut Checkpoint) -> Result<(), ZkpError> {
+        while let Some(block) = checkpoint.blocks.last().copied() {
+            // a missing block is one the new fork doesn't have yet
+            if self.block_hash(block.number).await? == Some(block.hash) {
+                return Ok(());
+            }
+            println!("subscription:{} block:{} was reorged, rewinding", subscription.name, block.number);
+            checkpoint.blocks.pop();
+        }
+        Ok(())
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<H256>, ZkpError> {
+        let block = self.client.get_block(number).await.map_err(|e| {
+            ZkpError::ChainRequestFailed(e.to_string())
+        })?;
+        Ok(block.and_then(|b| b.hash))
+    }
+
+    /// Polls until `shutdown` fires, a failed poll is logged and retried after `poll_interval`.
+    pub async fn run(&self, mut shutdown: oneshot::Receiver<()>) {
+        loop {
+            if let Err(e) = self.poll().await {
+                println!("event poll failed, err:{}", e);
+            }
+            if tokio::time::timeout(self.config.poll_interval, &mut shutdown).await.is_ok() {
+                return;
+            }
+        }
+    }
+}
+
+/// `config/contracts/contract.sol`'s event, for the tests.
+#[cfg(test)]
+#[derive(Debug, Clone, EthEvent)]
+#[ethevent(name = "ValueChanged", abi = "ValueChanged(address,string,string)")]
+pub struct ValueChanged {
+    #[ethevent(indexed)]
+    pub author: Address,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[test]
+pub fn test_decode_events() {
+    use ethers::abi::{encode, Token};
+
+    let subscription = EventSubscription::from_signatures("storage", vec![], &["event ValueChanged(address indexed author, string oldValue, string newValue)"]).expect("fail to parse");
+    let author = Address::repeat_byte(7);
+    let log = Log {
+        topics: vec![ValueChanged::signature(), H256::from(author)],
+        data: encode(&[Token::String(String::from("a")), Token::String(String::from("b"))]).into(),
+        block_number: Some(1u64.into()),
+        ..Default::default()
+    };
+
+    let event = subscription.decode(log.clone()).expect("fail to decode").expect("unknown event");
+    assert_eq!((event.event.as_str(), event.params.len()), ("ValueChanged", 3));
+    assert_eq!(event.params[2].value, Token::String(String::from("b")));
+
+    let seen = Arc::new(RwLock::new(Vec::new()));
+    let sink = seen.clone();
+    let handler = typed(move |e: ValueChanged, meta: &LogMeta| {
+        sink.write().unwrap().push((e.author, e.new_value, meta.block_number.as_u64()));
+        Ok(())
+    });
+    handler.handle(&event).expect("fail to handle");
+    assert_eq!(seen.read().unwrap().as_slice(), &[(author, String::from("b"), 1)]);
+
+    let other = Log { topics: vec![H256::repeat_byte(1)], ..log };
+    assert!(subscription.decode(other).expect("fail to decode").is_none());
+    assert!(EventSubscription::from_abi("verifier", vec![], include_str!("../config/contracts/KeyedVerifier.abi")).is_err());
+
+    // the provider isn't contacted before polling
+    let provider = ethers::providers::Provider::<ethers::providers::Http>::try_from("http://127.0.0.1:8545").unwrap();
+    let mut service = EventService::new(Arc::new(provider), EventServiceConfig::default(), Arc::new(MemoryCheckpointStore::default()));
+    service.subscribe(subscription.clone()).expect("fail to subscribe");
+    assert!(matches!(service.subscribe(subscription), Err(ZkpError::SubscriptionExists(_))));
+}
+
+#[test]
+pub fn test_file_checkpoints() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let path = std::env::temp_dir().join(format!("zkp-checkpoints-{}.json", nanos));
+    let checkpoint = Checkpoint { blocks: vec![BlockRef { number: 5, hash: H256::repeat_byte(5) }] };
+    FileCheckpointStore::new(&path).unwrap().save("storage", &checkpoint).expect("fail to save");
+
+    let store = FileCheckpointStore::new(&path).expect("fail to reopen");
+    assert_eq!(store.load("storage").unwrap(), Some(checkpoint));
+    assert_eq!(store.load("missing").unwrap(), None);
+    fs::remove_file(path).unwrap();
+}
 
 #[test]
-fn test_logs() {
-    tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap().block_on(async {
-        println!("1");
-        let ret = listen_deposit("").await.expect("failed");
-        println!("2");
+#[ignore = "needs anvil and solc"]
+pub fn test_event_service() {
+    use ethers::contract::ContractFactory;
+    use ethers::middleware::SignerMiddleware;
+    use ethers::providers::{Http, Provider};
+    use crate::anvil::LocalNode;
+
+    let node = LocalNode::for_tests();
+    let provider = Provider::<Http>::try_from(node.http_url()).unwrap();
+    let client = Arc::new(SignerMiddleware::new(provider.clone(), node.wallets()[0].clone()));
+    let sol = include_bytes!("../config/contracts/contract.sol");
+    let (abi, bytecode) = crate::contract_adapter::compile(sol, "SimpleStorage").expect("fail to compile");
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    rt.block_on(async {
+        let contract = ContractFactory::new(abi.clone(), bytecode, client.clone())
+            .deploy(String::from("initial")).unwrap()
+            .send().await.expect("fail to deploy");
+        let set_value = |value: &str| {
+            let call = contract.method::<_, ()>("setValue", value.to_string()).unwrap();
+            async move { call.send().await.unwrap().await.unwrap() }
+        };
+
+        let mut service = EventService::new(Arc::new(provider.clone()), EventServiceConfig { confirmations: 0, ..Default::default() }, Arc::new(MemoryCheckpointStore::default()));
+        let abi_json = serde_json::to_string(&abi).unwrap();
+        service.subscribe(EventSubscription::from_abi("storage", vec![contract.address()], abi_json.as_str()).unwrap()).unwrap();
+        let seen = Arc::new(RwLock::new(Vec::new()));
+        let sink = seen.clone();
+        service.on("storage", typed(move |e: ValueChanged, _: &LogMeta| {
+            sink.write().unwrap().push(e.new_value);
+            Ok(())
+        })).unwrap();
+
+        set_value("a").await;
+        assert_eq!(service.poll().await.expect("fail to poll"), 2);
+        // nothing new, the checkpoint holds
+        assert_eq!(service.poll().await.expect("fail to poll"), 0);
+
+        // replace the last block with another one
+        let snapshot: ethers::types::U256 = provider.request("evm_snapshot", ()).await.unwrap();
+        set_value("b").await;
+        assert_eq!(service.poll().await.expect("fail to poll"), 1);
+        let _: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+        set_value("c").await;
+        assert_eq!(service.poll().await.expect("fail to poll"), 1);
     });
-    thread::sleep(Duration::from_secs(5));
+    assert_eq!(seen.read().unwrap().as_slice(), &["initial", "a", "b", "c"]);
 }
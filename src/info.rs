@@ -0,0 +1,84 @@
+use std::convert::TryInto;
+use serde::{Serialize, Deserialize};
+use crate::contract_adapter::Deployment;
+use crate::errors::ZkpError;
+
+const HEADER_SECTION: u32 = 1;
+
+/// Signal counts from the header section of a circom `.r1cs` file.
+/// plonkit folds public outputs and inputs together, so they are read from the file itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct R1csHeader {
+    pub num_wires: u32,
+    pub num_public_outputs: u32,
+    pub num_public_inputs: u32,
+    pub num_private_inputs: u32,
+    pub num_constraints: u32,
+}
+
+impl R1csHeader {
+    pub fn parse(r1cs: &[u8]) -> Result<Self, ZkpError> {
+        let invalid = || ZkpError::InvalidR1cs(String::from("truncated r1cs header"));
+        if r1cs.len() < 12 || &r1cs[..4] != b"r1cs" {
+            return Err(ZkpError::InvalidR1cs(String::from("missing r1cs magic")));
+        }
+        let num_sections = read_u32(r1cs, 8).ok_or_else(invalid)?;
+        let mut pos = 12;
+        for _ in 0..num_sections {
+            let section_type = read_u32(r1cs, pos).ok_or_else(invalid)?;
+            let size = read_u64(r1cs, pos + 4).ok_or_else(invalid)? as usize;
+            pos += 12;
+            if section_type == HEADER_SECTION {
+                // field size, prime, then the counts
+                let n8 = read_u32(r1cs, pos).ok_or_else(invalid)? as usize;
+                let counts = pos + 4 + n8;
+                return Ok(Self {
+                    num_wires: read_u32(r1cs, counts).ok_or_else(invalid)?,
+                    num_public_outputs: read_u32(r1cs, counts + 4).ok_or_else(invalid)?,
+                    num_public_inputs: read_u32(r1cs, counts + 8).ok_or_else(invalid)?,
+                    num_private_inputs: read_u32(r1cs, counts + 12).ok_or_else(invalid)?,
+                    // skips the u64 label count
+                    num_constraints: read_u32(r1cs, counts + 24).ok_or_else(invalid)?,
+                });
+            }
+            pos += size;
+        }
+        Err(ZkpError::InvalidR1cs(String::from("r1cs has no header section")))
+    }
+}
+
+/// What the server loaded for a registered circuit, returned by `/circuits`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CircuitInfo {
+    pub key: String,
+    pub num_constraints: usize,
+    pub num_variables: usize,
+    pub num_public_inputs: usize,
+    pub num_outputs: usize,
+    pub num_private_inputs: usize,
+    /// PLONK domain size after transpilation
+    pub domain_size: usize,
+    pub setup_power: u32,
+    pub version: u32,
+    pub hash: String,
+    pub registered_at: u64,
+    pub witness_generator: bool,
+    pub deployment: Option<Deployment>,
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    bytes.get(pos..pos + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> Option<u64> {
+    bytes.get(pos..pos + 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+}
+
+#[test]
+pub fn test_parse_r1cs_header() {
+    let r1cs = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let header = R1csHeader::parse(r1cs.as_slice()).expect("fail to parse");
+    // c <== a * b with c public
+    assert_eq!(header, R1csHeader { num_wires: 4, num_public_outputs: 1, num_public_inputs: 0, num_private_inputs: 2, num_constraints: 1 });
+    assert!(R1csHeader::parse(&r1cs[..20]).is_err());
+}
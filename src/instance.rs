@@ -1,16 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, format, Formatter};
-use std::{error, fs, panic};
+use std::{fs, panic};
 use std::any::Any;
 use std::fs::{OpenOptions, read};
 use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Seek};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex, MutexGuard, RwLock};
-use crossbeam::channel::{Receiver, Select, Sender};
+use std::str::FromStr;
+use std::thread;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crossbeam::channel::{Receiver, Sender};
 use ethers::prelude::artifacts::BinaryOperator::LessThan;
 use ethers::utils::hex;
 use plonkit::bellman_ce::{Circuit, Engine, SynthesisError};
-use plonkit::bellman_ce::bn256::Bn256;
+use plonkit::bellman_ce::bn256::{Bn256, Fr};
 use plonkit::circom_circuit::{CircomCircuit, R1CS};
 use plonkit::{bellman_ce, plonk, reader};
 use plonkit::bellman_ce::plonk::better_cs::cs::PlonkCsWidth4WithNextStepParams;
@@ -20,92 +24,156 @@ use plonkit::reader::load_witness_from_array;
 use primitive_types::U256;
 use rocket_multipart_form_data::multer::bytes;
 use serde::{Serialize, Deserialize};
-use tokio::runtime::Runtime;
 use tokio::sync::oneshot;
 use crate::ZKPInstance;
+use crate::store;
+use crate::store::{CircuitMeta, CircuitRecord, CircuitStore, MemoryCircuitStore};
+use crate::srs;
+use crate::srs::{SetupKeyCatalog, SrsManager};
+use crate::errors::ZkpError;
+use crate::serialization;
+use crate::pool::ProvingPool;
+use crate::jobs::{JobManager, JobTicket, JobView};
+use crate::batch_verify;
+use crate::witness;
+use crate::witness::WitnessCalculator;
+use crate::info::{CircuitInfo, R1csHeader};
+use crate::contract_adapter::{Deployer, Deployment};
+use crate::verifier_contract::{OnChainVerifier, OnChainVerifyResponse};
 
-const MONOMIAL_KEY_FILE: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup/setup_2^10.key");
-const TEMPLATE_SOL: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/config/template.sol");
-const SAVE_TEMP_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/temp");
+/// Relative to the working directory, `ZKP_SETUP_DIR` points anywhere else.
+pub const SETUP_KEY_DIR: &'static str = "testdata/plonk/setup";
+// embedded so an installed binary doesn't need the checkout it was built from
+const TEMPLATE_SOL: &'static str = include_str!("../config/template.sol");
 const DEFAULT_TRANSCRIPT: &'static str = "keccak";
+const VERSION_SEPARATOR: char = '@';
 
-#[derive(Debug, Clone)]
-pub struct TempError;
+static SOL_RENDERS: AtomicU64 = AtomicU64::new(0);
 
-impl Display for TempError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "temp error")
-    }
-}
-
-impl error::Error for TempError {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        None
-    }
-}
-
-#[async_trait]
 pub trait ZKComponent: Prover + Verifier + Helper + Send + Sync {
-    async fn start_zk(self);
+    /// Serves commands until `shutdown` is called, blocking the calling thread.
+    fn start_zk(self) where Self: Sized;
+    fn shutdown(&self);
 }
 
 #[async_trait]
 pub trait Prover {
-    async fn async_prove(&self, req: ProveRequest) -> Result<ProveResponse, Error>;
-    fn prove(&self, req: ProveRequest) -> Result<ProveResponse, Error>;
+    async fn async_prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError>;
+    fn prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError>;
+    /// Queues the proof without waiting for it, progress and result are reported through `ticket`.
+    fn submit_job(&self, req: ProveRequest, ticket: Arc<JobTicket>) -> Result<(), ZkpError>;
+
+    /// Proves every request concurrently on the proving pool, results keep the order of `reqs`.
+    async fn async_prove_batch(&self, reqs: Vec<ProveRequest>) -> Vec<Result<ProveResponse, ZkpError>> where Self: Sync {
+        futures::future::join_all(reqs.into_iter().map(|req| self.async_prove(req))).await
+    }
+    fn prove_batch(&self, reqs: Vec<ProveRequest>) -> Vec<Result<ProveResponse, ZkpError>> where Self: Sync {
+        futures::executor::block_on(self.async_prove_batch(reqs))
+    }
 }
 
 #[async_trait]
 pub trait Verifier {
-    async fn async_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error>;
-    fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error>;
+    async fn async_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError>;
+    fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError>;
+    /// Verifies many proofs of the same circuit, results keep the order of `reqs`.
+    async fn async_verify_batch(&self, reqs: Vec<VerifyRequest>) -> Vec<Result<VerifyResponse, ZkpError>>;
+    fn verify_batch(&self, reqs: Vec<VerifyRequest>) -> Vec<Result<VerifyResponse, ZkpError>> where Self: Sync {
+        futures::executor::block_on(self.async_verify_batch(reqs))
+    }
 }
 
 pub trait Helper {
-    fn get_vk_and_sol(&self) -> Result<(Vec<u8>, Vec<u8>), Error>;
+    fn get_vk_and_sol(&self) -> Result<(Vec<u8>, Vec<u8>), ZkpError>;
+    /// Runs the circuit's witness generator on a json object of input signals, returns `.wtns` bytes.
+    fn calculate_witness(&self, inputs: &str) -> Result<Vec<u8>, ZkpError>;
+    /// Checks the length of `wtns` and evaluates every constraint without proving.
+    fn check_witness(&self, wtns: Vec<u8>) -> Result<(), ZkpError>;
+    fn info(&self, meta: &CircuitMeta) -> CircuitInfo;
+    fn verification_key(&self) -> VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>;
 }
 
 
 pub struct ZKPCircomInstance {
     pub sender: Sender<Cmd>,
-    receiver: Receiver<Cmd>,
+    /// only held by the worker, so sending fails once it has stopped
+    receiver: Option<Receiver<Cmd>>,
+    shutdown_sender: Sender<()>,
+    shutdown_receiver: Option<Receiver<()>>,
+    pool: Arc<ProvingPool>,
     pub r1cs: R1CS<Bn256>,
     pub key: String,
     pub prover: Arc<SetupForProver>,
     pub vk: VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>,
+    pub setup_power: u32,
+    pub domain_size: usize,
+    pub header: R1csHeader,
+    witness_calculator: Option<Arc<WitnessCalculator>>,
 }
 
 impl Clone for ZKPCircomInstance {
     fn clone(&self) -> Self {
         Self {
             sender: self.sender.clone(),
-            receiver: self.receiver.clone(),
+            receiver: None,
+            shutdown_sender: self.shutdown_sender.clone(),
+            shutdown_receiver: None,
+            pool: self.pool.clone(),
             r1cs: self.r1cs.clone(),
             key: self.key.clone(),
             prover: self.prover.clone(),
             vk: self.vk.clone(),
+            setup_power: self.setup_power,
+            domain_size: self.domain_size,
+            header: self.header,
+            witness_calculator: self.witness_calculator.clone(),
         }
     }
 }
 
 impl ZKPCircomInstance {
-    pub fn get(&self) -> (Vec<u8>, Vec<u8>) {
+    /// Loads the circom wasm used to compute witnesses from named inputs.
+    pub fn with_witness_calculator(mut self, wasm: &[u8]) -> Result<Self, ZkpError> {
+        // older circom runtimes don't report how many inputs they take, the r1cs header knows
+        let input_size = (self.header.num_public_inputs + self.header.num_private_inputs) as usize;
+        self.witness_calculator = Some(Arc::new(WitnessCalculator::new(wasm)?.with_input_size(input_size)));
+        Ok(self)
+    }
+
+    pub fn get(&self) -> Result<(Vec<u8>, Vec<u8>), ZkpError> {
         let mut vk_bytes = Vec::<u8>::new();
-        self.vk.clone().write(&mut vk_bytes).unwrap();
-        let path: String = SAVE_TEMP_PATH.to_string() + &(format!("{}.sol", self.key.clone()));
-        println!("path:{}", path);
-        bellman_vk_codegen::render_verification_key(&self.vk, TEMPLATE_SOL, path.clone().as_str());
-        let sol_bytes = fs::read(path.clone()).expect("fail");
-        // rm
-        fs::remove_file(path.clone());
-        (vk_bytes, sol_bytes)
-    }
-
-    fn do_prove(&self, req: ProveRequest) -> Result<ProveResponse, Error> {
-        let witness = req.wtns;
-        let witness = load_witness_from_array::<Bn256>(witness).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+        self.vk.clone().write(&mut vk_bytes)?;
+        // the codegen reads the template from a file and only writes to a file,
+        // every render gets its own pair so concurrent calls can't read each other's
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        let stem = std::env::temp_dir().join(format!("zkp-sol-{}-{}-{}", std::process::id(), nanos, SOL_RENDERS.fetch_add(1, Ordering::SeqCst)));
+        let (template, path) = (stem.with_extension("template.sol"), stem.with_extension("sol"));
+        fs::write(&template, TEMPLATE_SOL)?;
+        bellman_vk_codegen::render_verification_key(&self.vk, template.to_string_lossy().as_ref(), path.to_string_lossy().as_ref());
+        let sol_bytes = fs::read(&path);
+        let _ = fs::remove_file(&template);
+        let _ = fs::remove_file(&path);
+        Ok((vk_bytes, sol_bytes?))
+    }
+
+    /// Loads `wtns` and makes sure it satisfies the circuit, so a bad witness is reported
+    /// by constraint index instead of as an opaque proving failure.
+    pub fn load_witness(&self, wtns: Vec<u8>) -> Result<Vec<Fr>, ZkpError> {
+        let witness = load_witness_from_array::<Bn256>(wtns).map_err(|e| {
+            ZkpError::InvalidWitness(e.to_string())
         })?;
+        if witness.len() != self.r1cs.num_variables {
+            return Err(ZkpError::WitnessSizeMismatch { expected: self.r1cs.num_variables, actual: witness.len() });
+        }
+        if let Some(index) = witness::first_unsatisfied(&self.r1cs, witness.as_slice()) {
+            return Err(ZkpError::UnsatisfiedConstraint { index });
+        }
+        Ok(witness)
+    }
+
+    /// Proves `wtns` and checks the result against the vk before handing it out.
+    pub fn create_proof(&self, wtns: Vec<u8>) -> Result<Proof<Bn256, PlonkCsWidth4WithNextStepParams>, ZkpError> {
+        let witness = self.load_witness(wtns)?;
         let circuit = CircomCircuit {
             r1cs: self.r1cs.clone(),
             witness: Some(witness),
@@ -113,23 +181,26 @@ impl ZKPCircomInstance {
             aux_offset: plonk::AUX_OFFSET,
         };
         let proof = self.prover.prove(circuit, DEFAULT_TRANSCRIPT).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+            ZkpError::ProvingFailed(e.to_string())
         })?;
         let b = plonk::verify(&self.vk.clone(), &proof, DEFAULT_TRANSCRIPT).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+            ZkpError::ProvingFailed(e.to_string())
         })?;
         if !b {
-            return Err(Error::new(ErrorKind::InvalidData, TempError {}));
+            return Err(ZkpError::ProofSelfCheckFailed);
         }
+        Ok(proof)
+    }
+
+    pub fn do_prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError> {
+        let proof = self.create_proof(req.wtns)?;
         let (inputs, serialized_proof) = bellman_vk_codegen::serialize_proof(&proof);
         let ser_proof_str = serde_json::to_string_pretty(&serialized_proof).unwrap();
         let ser_inputs_str = serde_json::to_string_pretty(&inputs).unwrap();
         let vv: Vec<U256> = serde_json::from_str(ser_proof_str.clone().as_str()).unwrap();
         assert_eq!(vv, serialized_proof);
         let mut proof_bytes = Vec::<u8>::new();
-        proof.write(&mut proof_bytes).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
-        })?;
+        proof.write(&mut proof_bytes)?;
 
         Ok(ProveResponse {
             proof: proof_bytes.clone(),
@@ -140,79 +211,182 @@ impl ZKPCircomInstance {
         })
     }
 
-    fn do_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error> {
-        let proof = reader::load_proof_from_bytes::<Bn256>(req.proof_bytes);
-        let v = plonk::verify(&self.vk.clone(), &proof, DEFAULT_TRANSCRIPT).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
-        })?;
-        Ok(VerifyResponse { verify: v })
+    fn do_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError> {
+        verify_with_vk(&self.vk, &req)
+    }
+}
+
+/// Decodes the proof of `req`, `None` when it doesn't carry the expected public inputs.
+fn decode_proof(vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>, req: &VerifyRequest) -> Result<Option<Proof<Bn256, PlonkCsWidth4WithNextStepParams>>, ZkpError> {
+    let proof = match &req.serialized_proof {
+        Some(serialized_proof) => {
+            let inputs = req.inputs.as_ref().ok_or_else(|| {
+                ZkpError::InvalidInputs(String::from("public inputs are required for a serialized proof"))
+            })?;
+            serialization::deserialize_proof(vk, inputs, serialized_proof)?
+        }
+        None => Proof::<Bn256, PlonkCsWidth4WithNextStepParams>::read(req.proof_bytes.as_slice()).map_err(|e| {
+            ZkpError::InvalidProof(e.to_string())
+        })?,
+    };
+    if let Some(expected) = &req.inputs {
+        // same layout as the public_inputs argument of the on-chain verifier
+        let (inputs, _) = bellman_vk_codegen::serialize_proof(&proof);
+        if &inputs != expected {
+            return Ok(None);
+        }
     }
+    Ok(Some(proof))
+}
+
+/// The `(public_inputs, serialized_proof)` arguments of the on-chain verifier for `req`.
+/// Expected inputs are passed instead of the proof's own, so a mismatch is rejected on-chain as well.
+pub fn solidity_args(req: &VerifyRequest) -> Result<(Vec<U256>, Vec<U256>), ZkpError> {
+    match &req.serialized_proof {
+        Some(serialized_proof) => {
+            let inputs = req.inputs.clone().ok_or_else(|| {
+                ZkpError::InvalidInputs(String::from("public inputs are required for a serialized proof"))
+            })?;
+            Ok((inputs, serialized_proof.clone()))
+        }
+        None => {
+            let proof = Proof::<Bn256, PlonkCsWidth4WithNextStepParams>::read(req.proof_bytes.as_slice()).map_err(|e| {
+                ZkpError::InvalidProof(e.to_string())
+            })?;
+            let (inputs, serialized_proof) = bellman_vk_codegen::serialize_proof(&proof);
+            Ok((req.inputs.clone().unwrap_or(inputs), serialized_proof))
+        }
+    }
+}
+
+/// Checks `req` against `vk` alone, no r1cs, setup key or registered circuit involved. `req.key` is ignored.
+pub fn verify_with_vk(vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>, req: &VerifyRequest) -> Result<VerifyResponse, ZkpError> {
+    let proof = match decode_proof(vk, req)? {
+        Some(proof) => proof,
+        None => return Ok(VerifyResponse { verify: false }),
+    };
+    let v = plonk::verify(vk, &proof, DEFAULT_TRANSCRIPT).map_err(|e| {
+        ZkpError::InvalidProof(e.to_string())
+    })?;
+    Ok(VerifyResponse { verify: v })
 }
 
 // TODO,这里的,全丢到async fn中
 #[async_trait]
 impl Prover for ZKPCircomInstance {
-    fn prove(&self, req: ProveRequest) -> Result<ProveResponse, Error> {
+    fn prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError> {
         futures::executor::block_on(self.async_prove(req))
     }
 
-    async fn async_prove(&self, req: ProveRequest) -> Result<ProveResponse, Error> {
+    async fn async_prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError> {
         let (ts, mut rs) = oneshot::channel();
         self.sender.send(Cmd::new(Operation::Prove(req), ts)).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+            ZkpError::WorkerUnavailable(e.to_string())
         })?;
 
-        rs.await.map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
-        }).map(|v| {
-            if let ResultOperation::Proof(value) = v {
-                value
-            } else {
-                unreachable!()
-            }
+        let v = rs.await.map_err(|e| {
+            ZkpError::WorkerUnavailable(e.to_string())
+        })?;
+        match v {
+            ResultOperation::Proof(value) => Ok(value),
+            ResultOperation::Fail(e) => Err(e),
+            _ => unreachable!(),
+        }
+    }
+
+    fn submit_job(&self, req: ProveRequest, ticket: Arc<JobTicket>) -> Result<(), ZkpError> {
+        // nobody waits on the reply channel, the worker reports to the ticket instead
+        let (ts, _) = oneshot::channel();
+        self.sender.send(Cmd::with_ticket(Operation::Prove(req), ts, ticket)).map_err(|e| {
+            ZkpError::WorkerUnavailable(e.to_string())
         })
     }
 }
 
 #[async_trait]
 impl Verifier for ZKPCircomInstance {
-    async fn async_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error> {
+    async fn async_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError> {
         let (ts, mut rs) = oneshot::channel();
         self.sender.send(Cmd::new(Operation::Verify(req), ts)).map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+            ZkpError::WorkerUnavailable(e.to_string())
         })?;
-        rs.await.map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
-        }).map(|v| {
-            if let ResultOperation::Verify(value) = v {
-                value
-            } else {
-                unreachable!()
-            }
-        })
+        let v = rs.await.map_err(|e| {
+            ZkpError::WorkerUnavailable(e.to_string())
+        })?;
+        match v {
+            ResultOperation::Verify(value) => Ok(value),
+            ResultOperation::Fail(e) => Err(e),
+            _ => unreachable!(),
+        }
     }
 
-    fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error> {
+    fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError> {
         futures::executor::block_on(self.async_verify(req))
     }
+
+    async fn async_verify_batch(&self, reqs: Vec<VerifyRequest>) -> Vec<Result<VerifyResponse, ZkpError>> {
+        // the common case of an all valid batch costs a single pairing
+        let (ts, rs) = oneshot::channel();
+        if self.sender.send(Cmd::new(Operation::VerifyBatch(reqs.clone()), ts)).is_ok() {
+            if let Ok(ResultOperation::VerifyBatch(true)) = rs.await {
+                return reqs.iter().map(|_| Ok(VerifyResponse { verify: true })).collect();
+            }
+        }
+        // something in the batch is off, check one by one to report which
+        futures::future::join_all(reqs.into_iter().map(|req| self.async_verify(req))).await
+    }
 }
 
 impl Helper for ZKPCircomInstance {
-    fn get_vk_and_sol(&self) -> Result<(Vec<u8>, Vec<u8>), Error> {
-        Ok(self.get())
+    fn get_vk_and_sol(&self) -> Result<(Vec<u8>, Vec<u8>), ZkpError> {
+        self.get()
+    }
+
+    fn calculate_witness(&self, inputs: &str) -> Result<Vec<u8>, ZkpError> {
+        let calculator = self.witness_calculator.as_ref().ok_or_else(|| ZkpError::NoWitnessCalculator(self.key.clone()))?;
+        calculator.calculate_wtns(inputs)
+    }
+
+    fn check_witness(&self, wtns: Vec<u8>) -> Result<(), ZkpError> {
+        self.load_witness(wtns).map(|_| ())
+    }
+
+    fn verification_key(&self) -> VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams> {
+        self.vk.clone()
+    }
+
+    fn info(&self, meta: &CircuitMeta) -> CircuitInfo {
+        CircuitInfo {
+            key: meta.key.clone(),
+            num_constraints: self.r1cs.constraints.len(),
+            num_variables: self.r1cs.num_variables,
+            num_public_inputs: self.header.num_public_inputs as usize,
+            num_outputs: self.header.num_public_outputs as usize,
+            num_private_inputs: self.header.num_private_inputs as usize,
+            domain_size: self.domain_size,
+            setup_power: self.setup_power,
+            version: meta.version,
+            hash: meta.r1cs_hash.clone(),
+            registered_at: meta.registered_at,
+            witness_generator: self.witness_calculator.is_some(),
+            deployment: meta.deployment.clone(),
+        }
     }
 }
 
 pub enum Operation {
     Prove(ProveRequest),
     Verify(VerifyRequest),
+    VerifyBatch(Vec<VerifyRequest>),
 }
 
 #[derive(Debug)]
 pub enum ResultOperation {
     Proof(ProveResponse),
     Verify(VerifyResponse),
-    Fail(Error),
+    /// whether every proof of the batch passed the combined check
+    VerifyBatch(bool),
+    Fail(ZkpError),
 }
 
 pub trait Event: Send + Sync {}
@@ -221,6 +395,7 @@ pub struct Cmd
 {
     pub op: Operation,
     pub sender: oneshot::Sender<ResultOperation>,
+    pub ticket: Option<Arc<JobTicket>>,
 }
 
 unsafe impl Send for Cmd {}
@@ -230,170 +405,555 @@ unsafe impl Sync for Cmd {}
 impl Cmd where
 {
     pub fn new(op: Operation, sender: oneshot::Sender<ResultOperation>) -> Self {
-        Self { op, sender }
+        Self { op, sender, ticket: None }
+    }
+
+    pub fn with_ticket(op: Operation, sender: oneshot::Sender<ResultOperation>, ticket: Arc<JobTicket>) -> Self {
+        Self { op, sender, ticket: Some(ticket) }
     }
 }
 
+/// A batch verification spread over the pool, see `dispatch_batch`.
+struct PendingBatch {
+    parts: Mutex<Vec<Option<Result<Option<(Proof<Bn256, PlonkCsWidth4WithNextStepParams>, batch_verify::Aggregate)>, ZkpError>>>>,
+    remaining: AtomicUsize,
+    sender: Mutex<Option<oneshot::Sender<ResultOperation>>>,
+}
 
-#[async_trait]
 impl ZKComponent for ZKPCircomInstance {
-    async fn start_zk(self) {
-        let clone_sub = self.receiver.clone();
-        let mut sel = Select::new();
-        sel.recv(&clone_sub);
+    fn start_zk(mut self) {
+        let (receiver, shutdown_receiver) = match (self.receiver.take(), self.shutdown_receiver.take()) {
+            (Some(receiver), Some(shutdown_receiver)) => (receiver, shutdown_receiver),
+            _ => {
+                println!("circuit:{} worker is already running", self.key);
+                return;
+            }
+        };
+        // commands are only received here, the proving itself runs on the shared pool
+        let instance = Arc::new(self);
         loop {
-            let res = clone_sub.try_recv();
-            // If the operation turns out not to be ready, retry.
-            if let Err(e) = res {
-                if e.is_empty() {
-                    continue;
+            crossbeam::select! {
+                recv(receiver) -> cmd => match cmd {
+                    Ok(cmd) => instance.dispatch(cmd),
+                    Err(_) => break,
+                },
+                recv(shutdown_receiver) -> _ => break,
+            }
+        }
+        // the circuit is gone, answer whatever was still queued instead of leaving callers and jobs hanging.
+        // `receiver` is dropped on return, after that sending to the circuit fails with WorkerUnavailable
+        for Cmd { sender, ticket, .. } in receiver.try_iter() {
+            let ret = ResultOperation::Fail(ZkpError::WorkerUnavailable(format!("circuit {} was removed", instance.key)));
+            if let Some(ticket) = &ticket {
+                ticket.finish(&ret);
+            }
+            let _ = sender.send(ret);
+        }
+        println!("circuit:{} worker stopped", instance.key);
+    }
+
+    fn shutdown(&self) {
+        let _ = self.shutdown_sender.try_send(());
+    }
+}
+
+impl ZKPCircomInstance {
+    fn dispatch(self: &Arc<Self>, cmd: Cmd) {
+        let instance = self.clone();
+        let Cmd { op, sender, ticket } = cmd;
+        let handle: Box<dyn FnOnce(&Self) -> ResultOperation + Send> = match op {
+            Operation::Prove(req) => Box::new(move |instance: &Self| match instance.do_prove(req) {
+                Ok(resp) => ResultOperation::Proof(resp),
+                Err(e) => ResultOperation::Fail(e),
+            }),
+            Operation::Verify(req) => Box::new(move |instance: &Self| match instance.do_verify(req) {
+                Ok(resp) => ResultOperation::Verify(resp),
+                Err(e) => ResultOperation::Fail(e),
+            }),
+            Operation::VerifyBatch(reqs) => return self.dispatch_batch(reqs, sender),
+        };
+        // if the pool is gone the job is dropped together with `sender`, and the caller sees WorkerUnavailable
+        let _ = self.pool.execute(move || {
+            if let Some(ticket) = &ticket {
+                // cancelled while it was queued
+                if !ticket.start() {
+                    return;
                 }
             }
-            let cmd: Cmd = res.unwrap();
-            let mut send_ret: Option<ResultOperation> = None;
-
-            match cmd.op {
-                Operation::Prove(value) => {
-                    let mut res = self.do_prove(value);
-                    match res {
-                        Ok(prove_resp) => {
-                            send_ret = Some(ResultOperation::Proof(prove_resp));
-                        }
-                        Err(e) => {
-                            send_ret = Some(ResultOperation::Fail(e));
-                        }
+            // the pool survives a panicking proof, but the job still has to end up failed instead of running forever
+            let ret = panic::catch_unwind(panic::AssertUnwindSafe(|| handle(instance.as_ref()))).unwrap_or_else(|e| {
+                let reason = e.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| e.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| String::from("prover panicked"));
+                ResultOperation::Fail(ZkpError::ProvingFailed(reason))
+            });
+            if let Some(ticket) = &ticket {
+                ticket.finish(&ret);
+            }
+            let _ = sender.send(ret);
+        });
+    }
+
+    /// Aggregates every proof of the batch on its own pool job, the job finishing last folds them all into one pairing.
+    /// A job that panics or never runs drops its handle on `sender`, and the caller falls back to verifying one by one.
+    fn dispatch_batch(self: &Arc<Self>, reqs: Vec<VerifyRequest>, sender: oneshot::Sender<ResultOperation>) {
+        if reqs.is_empty() {
+            let _ = sender.send(ResultOperation::VerifyBatch(true));
+            return;
+        }
+        let batch = Arc::new(PendingBatch {
+            parts: Mutex::new(reqs.iter().map(|_| None).collect()),
+            remaining: AtomicUsize::new(reqs.len()),
+            sender: Mutex::new(Some(sender)),
+        });
+        for (index, req) in reqs.into_iter().enumerate() {
+            let instance = self.clone();
+            let batch = batch.clone();
+            let _ = self.pool.execute(move || {
+                let part = decode_proof(&instance.vk, &req).and_then(|proof| match proof {
+                    Some(proof) => batch_verify::aggregate(&instance.vk, &proof).map(|aggregate| aggregate.map(|aggregate| (proof, aggregate))),
+                    None => Ok(None),
+                });
+                batch.parts.lock().unwrap()[index] = Some(part);
+                if batch.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    let ret = instance.fold_batch(&batch);
+                    if let Some(sender) = batch.sender.lock().unwrap().take() {
+                        let _ = sender.send(ret);
                     }
                 }
-                Operation::Verify(value) => {
-                    let mut res = self.do_verify(value);
-                    match res {
-                        Ok(resp) => {
-                            send_ret = Some(ResultOperation::Verify(resp));
-                        }
-                        Err(e) => {
-                            send_ret = Some(ResultOperation::Fail(e));
-                        }
-                    }
+            });
+        }
+    }
+
+    fn fold_batch(&self, batch: &PendingBatch) -> ResultOperation {
+        let parts = std::mem::take(&mut *batch.parts.lock().unwrap());
+        let mut proofs = Vec::with_capacity(parts.len());
+        let mut aggregates = Vec::with_capacity(parts.len());
+        for part in parts {
+            match part.expect("every proof is aggregated before the batch is folded") {
+                Ok(Some((proof, aggregate))) => {
+                    proofs.push(proof);
+                    aggregates.push(aggregate);
                 }
-                _ => {}
-            }
-            if let Some(v) = send_ret {
-                cmd.sender.send(v).expect("fail to send");
+                Ok(None) => return ResultOperation::VerifyBatch(false),
+                Err(e) => return ResultOperation::Fail(e),
             }
         }
+        match batch_verify::verify_aggregates(&self.vk, proofs.as_slice(), aggregates.as_slice()) {
+            Ok(all_valid) => ResultOperation::VerifyBatch(all_valid),
+            Err(e) => ResultOperation::Fail(e),
+        }
     }
 }
 
-#[derive(Default)]
-pub struct ZKPFactory {}
+#[derive(Clone)]
+pub struct ZKPFactory {
+    srs: Arc<SrsManager>,
+    catalog: Arc<SetupKeyCatalog>,
+    pool: Arc<ProvingPool>,
+}
+
+impl Default for ZKPFactory {
+    fn default() -> Self {
+        let catalog = SetupKeyCatalog::scan(SETUP_KEY_DIR).unwrap_or_default();
+        Self::new(Arc::new(SrsManager::default()), Arc::new(catalog), Arc::new(ProvingPool::default()))
+    }
+}
 
 impl ZKPFactory {
-    pub fn build(self, id: String, r: Vec<u8>) -> ZKPCircomInstance {
-        let res = self.build_with_key_type(MONOMIAL_KEY_FILE, id.clone(), r.clone());
-        if let Err(e) = res {
-            let new_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup/setup_2^20.key");
-            self.build_with_key_type(new_file, id.clone(), r.clone()).unwrap()
-        } else {
-            return res.unwrap();
-        }
-    }
-    // TODO: pass runtime
-    pub fn build_and_start(self, rt: Arc<Runtime>, id: String, r: Vec<u8>) -> Box<dyn ZKComponent> {
-        let ret = self.build(id, r);
-        let v = ret.clone();
-        rt.clone().spawn(async move {
-            v.clone().start_zk().await
-        });
-        return Box::new(ret.clone());
+    pub fn new(srs: Arc<SrsManager>, catalog: Arc<SetupKeyCatalog>, pool: Arc<ProvingPool>) -> Self {
+        Self { srs, catalog, pool }
     }
 
-    fn build_with_key_type(&self, path: &str, id: String, r: Vec<u8>) -> Result<ZKPCircomInstance, Error> {
-        let reader = Cursor::new(r);
-        let (r1cs, _) = reader::load_r1cs_from_bin(reader);
+    pub fn build(&self, id: String, r: Vec<u8>) -> Result<ZKPCircomInstance, ZkpError> {
+        let header = R1csHeader::parse(r.as_slice())?;
+        // the plonkit reader panics on malformed input instead of returning an error
+        let (r1cs, _) = panic::catch_unwind(|| reader::load_r1cs_from_bin(Cursor::new(r))).map_err(|_| {
+            ZkpError::InvalidR1cs(String::from("fail to parse r1cs file"))
+        })?;
         let circuit = CircomCircuit {
             r1cs: r1cs.clone(),
             witness: None,
             wire_mapping: None,
             aux_offset: plonk::AUX_OFFSET,
         };
+        let domain_size = srs::domain_size(circuit.clone())?;
+        let (power, path) = self.catalog.pick(domain_size)?;
+        self.build_with_key_type(path.to_string_lossy().as_ref(), power, domain_size, header, id, circuit)
+    }
+    pub fn build_and_start(&self, id: String, r: Vec<u8>, wasm: Option<&[u8]>) -> Result<Box<dyn ZKComponent>, ZkpError> {
+        let mut ret = self.build(id.clone(), r)?;
+        if let Some(wasm) = wasm {
+            ret = ret.with_witness_calculator(wasm)?;
+        }
+        let mut worker = ret.clone();
+        worker.receiver = ret.receiver.take();
+        worker.shutdown_receiver = ret.shutdown_receiver.take();
+        // the worker blocks on its channel, so it gets a thread of its own instead of a runtime task
+        thread::Builder::new()
+            .name(format!("zkp-worker-{}", id.replace('\0', "")))
+            .spawn(move || worker.start_zk())?;
+        Ok(Box::new(ret))
+    }
 
+    fn build_with_key_type(&self, path: &str, power: u32, domain_size: usize, header: R1csHeader, id: String, circuit: CircomCircuit<Bn256>) -> Result<ZKPCircomInstance, ZkpError> {
+        let key_lagrange_form = self.srs.lagrange_form(path, domain_size)?;
         let setup = plonk::SetupForProver::prepare_setup_for_prover(
             circuit.clone(),
-            reader::load_key_monomial_form(path),
-            reader::maybe_load_key_lagrange_form(None),
-        )
-            .unwrap();
-
-        let res = panic::catch_unwind(|| {
-            let _ = setup.get_srs_lagrange_form_from_monomial_form();
-        });
-        if res.is_err() {
-            return Err(Error::new(ErrorKind::InvalidData, TempError));
-        }
+            self.srs.monomial_form(path),
+            Some(key_lagrange_form),
+        ).map_err(|e| {
+            ZkpError::InvalidR1cs(e.to_string())
+        })?;
 
         let vk = setup.make_verification_key().map_err(|e| {
-            Error::new(ErrorKind::InvalidData, e)
+            ZkpError::InvalidR1cs(e.to_string())
         })?;
 
         let (sender, receiver) = crossbeam::channel::bounded::<Cmd>(10);
-        Ok(ZKPCircomInstance { sender: sender.clone(), receiver: receiver.clone(), r1cs: r1cs.clone(), key: id, prover: Arc::new(setup), vk: (vk.clone() as VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>) })
+        let (shutdown_sender, shutdown_receiver) = crossbeam::channel::bounded::<()>(1);
+        Ok(ZKPCircomInstance { sender, receiver: Some(receiver), shutdown_sender, shutdown_receiver: Some(shutdown_receiver), pool: self.pool.clone(), r1cs: circuit.r1cs.clone(), key: id, prover: Arc::new(setup), vk: (vk.clone() as VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>), setup_power: power, domain_size, header, witness_calculator: None })
     }
 }
 
 
+#[derive(Clone)]
+struct Registered {
+    instance: Arc<dyn ZKComponent>,
+    meta: CircuitMeta,
+}
+
+/// Separates the optional pin of `key@version` or `key@<r1cs hash>`, a bare key means the latest version.
+pub fn split_key(key: &str) -> (&str, Option<&str>) {
+    match key.split_once(VERSION_SEPARATOR) {
+        Some((name, pin)) => (name, Some(pin)),
+        None => (key, None),
+    }
+}
+
+/// Registered circuits by key, every key holds its versions in order. The map lock is only held to look an instance up,
+/// proving and verifying go through the instance itself so different keys never wait on each other.
 pub struct ZKPProverContainer {
-    mutex: RwLock<HashMap<String, Arc<Mutex<Box<dyn ZKComponent>>>>>,
-    rt: Arc<Runtime>,
+    mutex: RwLock<HashMap<String, BTreeMap<u32, Registered>>>,
+    /// serializes persisting new registrations, so the map lock isn't held while writing to the store
+    registering: Mutex<()>,
+    store: Arc<dyn CircuitStore>,
+    factory: ZKPFactory,
+    jobs: JobManager,
 }
 
 impl Default for ZKPProverContainer {
     fn default() -> Self {
         Self {
             mutex: Default::default(),
-            rt: Arc::new(tokio::runtime::Builder::new_multi_thread().enable_time().enable_io().build().unwrap()),
+            registering: Default::default(),
+            store: Arc::new(MemoryCircuitStore::default()),
+            factory: ZKPFactory::default(),
+            jobs: JobManager::default(),
+        }
+    }
+}
+
+impl Drop for ZKPProverContainer {
+    fn drop(&mut self) {
+        for registered in self.mutex.read().unwrap().values().flat_map(|versions| versions.values()) {
+            registered.instance.shutdown();
         }
     }
 }
 
 impl ZKPProverContainer {
-    pub fn register(&mut self, req: RegisterRequest) -> RegisterResponse {
+    /// Creates a container backed by `store` and rebuilds every circuit already persisted in it.
+    /// Records that fail to load or build are logged and skipped, the others are still served.
+    pub fn with_store(store: Arc<dyn CircuitStore>, factory: ZKPFactory) -> Self {
+        let mut container = Self::default();
+        container.store = store;
+        container.factory = factory;
+        container.restore();
+        container
+    }
+
+    fn restore(&mut self) {
+        let records = match self.store.load_all() {
+            Ok(records) => records,
+            Err(e) => {
+                println!("fail to load stored circuits, err:{}", e);
+                return;
+            }
+        };
         let mut cache = self.mutex.write().unwrap();
-        let instance = cache.entry(req.key.clone()).or_insert(
-            Arc::new(Mutex::new(ZKPFactory::default().build_and_start(self.rt.clone(), req.key.clone(), req.reader)))
-        );
-        let (vk, sol) = instance.clone().lock().unwrap().get_vk_and_sol().unwrap();
+        for record in records {
+            let key = record.meta.key.clone();
+            let instance = match self.factory.build_and_start(key.clone(), record.r1cs, record.wasm.as_deref()) {
+                Ok(instance) => instance,
+                Err(e) => {
+                    println!("skip circuit:{} version:{}, fail to restore, err:{}", key, record.meta.version, e);
+                    continue;
+                }
+            };
+            let vk = match instance.get_vk_and_sol() {
+                Ok((vk, _)) => vk,
+                Err(e) => {
+                    println!("skip circuit:{} version:{}, fail to render vk, err:{}", key, record.meta.version, e);
+                    instance.shutdown();
+                    continue;
+                }
+            };
+            if vk != record.vk {
+                println!("circuit:{} version:{} vk differs from the stored one, the setup key may have changed", key, record.meta.version);
+            }
+            cache.entry(key).or_default().insert(record.meta.version, Registered { instance: Arc::from(instance), meta: record.meta });
+        }
+    }
+
+    /// Builds and starts the circuit, what happens to an existing circuit of the same key
+    /// is up to `req.mode`. Registering an r1cs the key already has a version of returns that version.
+    /// The setup runs without holding the map lock, this can take seconds for big circuits.
+    pub fn register(&self, req: RegisterRequest) -> Result<RegisterResponse, ZkpError> {
+        // the key names the worker thread and the store directory
+        if req.key.is_empty() || req.key.len() > store::MAX_KEY_LEN || req.key.chars().any(|c| c == VERSION_SEPARATOR || c.is_control()) {
+            return Err(ZkpError::InvalidCircuitKey(req.key));
+        }
+        let hash = store::r1cs_hash(req.reader.as_slice());
+        let current = self.mutex.read().unwrap().get(req.key.as_str()).cloned().unwrap_or_default();
+        if let Some(existing) = current.values().find(|r| r.meta.r1cs_hash == hash) {
+            return Self::registered(existing);
+        }
+        Self::next_version(&current, req.key.as_str(), req.mode)?;
+
+        let built: Arc<dyn ZKComponent> = Arc::from(self.factory.build_and_start(req.key.clone(), req.reader.clone(), req.wasm.as_deref())?);
+        let (vk, _) = match built.get_vk_and_sol() {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                built.shutdown();
+                return Err(e);
+            }
+        };
+
+        // the key may have been registered or deregistered while building, decide again on what is there now
+        let registering = self.registering.lock().unwrap();
+        let current = self.mutex.read().unwrap().get(req.key.as_str()).cloned().unwrap_or_default();
+        if let Some(existing) = current.values().find(|r| r.meta.r1cs_hash == hash) {
+            built.shutdown();
+            return Self::registered(existing);
+        }
+        let version = match Self::next_version(&current, req.key.as_str(), req.mode) {
+            Ok(version) => version,
+            Err(e) => {
+                built.shutdown();
+                return Err(e);
+            }
+        };
+        let record = CircuitRecord { meta: CircuitMeta::new(req.key.clone(), req.reader.as_slice(), version), r1cs: req.reader, vk, wasm: req.wasm };
+        // a circuit that would be gone after a restart is not registered
+        if let Err(e) = self.store.save(&record) {
+            println!("fail to persist circuit:{}, err:{}", req.key, e);
+            built.shutdown();
+            return Err(e.into());
+        }
+        let registered = Registered { instance: built, meta: record.meta };
+        let replaced = self.mutex.write().unwrap().entry(req.key.clone()).or_default().insert(version, registered.clone());
+        drop(registering);
+        if let Some(replaced) = replaced {
+            replaced.instance.shutdown();
+        }
+        Self::registered(&registered)
+    }
+
+    fn next_version(current: &BTreeMap<u32, Registered>, key: &str, mode: RegisterMode) -> Result<u32, ZkpError> {
+        match (current.values().next_back(), mode) {
+            (Some(_), RegisterMode::Reject) => Err(ZkpError::CircuitExists(key.to_string())),
+            (Some(latest), RegisterMode::Overwrite) => Ok(latest.meta.version),
+            (Some(latest), RegisterMode::NewVersion) => Ok(latest.meta.version + 1),
+            (None, _) => Ok(1),
+        }
+    }
+
+    fn registered(registered: &Registered) -> Result<RegisterResponse, ZkpError> {
+        let (vk, sol) = registered.instance.get_vk_and_sol()?;
         let v = String::from_utf8_lossy(sol.as_slice()).to_string();
-        RegisterResponse { vk: vk, sol: v }
+        Ok(RegisterResponse { vk: vk, sol: v, version: registered.meta.version, hash: registered.meta.r1cs_hash.clone() })
     }
-    pub fn prove(&self, req: ProveRequest) -> Result<ProveResponse, Error> {
-        let cache = self.mutex.read().unwrap();
-        if let Some(instance) = cache.get(req.key.as_str()) {
-            let v = instance.lock().unwrap();
-            v.prove(req.clone())
-        } else {
-            Err(Error::new(ErrorKind::InvalidData, TempError {}))
+
+    /// Stops the workers of every version of `key`, or of the pinned one only, and forgets them.
+    /// Queued requests fail with WorkerUnavailable.
+    pub fn deregister(&self, key: &str) -> Result<Vec<CircuitInfo>, ZkpError> {
+        let (name, pin) = split_key(key);
+        let _registering = self.registering.lock().unwrap();
+        let removed: Vec<Registered> = {
+            let mut cache = self.mutex.write().unwrap();
+            let versions = cache.get_mut(name).ok_or_else(|| ZkpError::UnknownCircuit(key.to_string()))?;
+            let removed = match pin {
+                Some(pin) => {
+                    let version = Self::find(versions, pin).map(|r| r.meta.version).ok_or_else(|| ZkpError::UnknownCircuit(key.to_string()))?;
+                    versions.remove(&version).into_iter().collect()
+                }
+                None => std::mem::take(versions).into_values().collect(),
+            };
+            if versions.is_empty() {
+                cache.remove(name);
+            }
+            removed
+        };
+        // stop every worker before touching the store, a failing remove must not leave one running
+        for registered in removed.iter() {
+            registered.instance.shutdown();
+        }
+        let mut failed = None;
+        for registered in removed.iter() {
+            if let Err(e) = self.store.remove(name, registered.meta.version) {
+                println!("fail to remove circuit:{} version:{} from the store, err:{}", name, registered.meta.version, e);
+                failed.get_or_insert(e);
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e.into());
+        }
+        Ok(removed.iter().map(|r| r.instance.info(&r.meta)).collect())
+    }
+
+    /// Deploys the solidity verifier of `key`, which may pin a version, and records the contract address
+    /// in the version's meta. A circuit deployed before is deployed again, e.g. after switching chains.
+    pub async fn deploy(&self, key: &str, deployer: &Deployer) -> Result<CircuitInfo, ZkpError> {
+        let registered = self.lookup(key)?;
+        let (_, sol) = registered.instance.get_vk_and_sol()?;
+        let deployment = deployer.deploy(sol).await?;
+        println!("circuit:{} version:{} verifier deployed at:{:?}", registered.meta.key, registered.meta.version, deployment.address);
+        self.record_deployment(&registered.meta, deployment)
+    }
+
+    /// Asks the verifier deployed for `req.key` instead of verifying locally, see `OnChainVerifier::verify`.
+    pub async fn verify_onchain(&self, req: VerifyRequest, verifier: &OnChainVerifier, transact: bool) -> Result<OnChainVerifyResponse, ZkpError> {
+        let registered = self.lookup(req.key.as_str())?;
+        let deployment = registered.meta.deployment.ok_or_else(|| ZkpError::NotDeployed(req.key.clone()))?;
+        // deployed before switching chains, the address means nothing on this one
+        let chain_id = verifier.chain_id().await?;
+        if deployment.chain_id != chain_id {
+            return Err(ZkpError::NotDeployed(format!("{} on chain {}", req.key, chain_id)));
         }
+        let (inputs, serialized_proof) = solidity_args(&req)?;
+        verifier.verify(deployment.address, inputs.as_slice(), serialized_proof.as_slice(), transact).await
     }
-    pub fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, Error> {
+
+    fn record_deployment(&self, meta: &CircuitMeta, deployment: Deployment) -> Result<CircuitInfo, ZkpError> {
+        let mut cache = self.mutex.write().unwrap();
+        // the version may have been overwritten or removed while deploying
+        let registered = cache.get_mut(meta.key.as_str())
+            .and_then(|versions| versions.get_mut(&meta.version))
+            .filter(|r| r.meta.r1cs_hash == meta.r1cs_hash)
+            .ok_or_else(|| ZkpError::UnknownCircuit(format!("{}{}{}", meta.key, VERSION_SEPARATOR, meta.r1cs_hash)))?;
+        registered.meta.deployment = Some(deployment);
+        if let Err(e) = self.store.save_meta(&registered.meta) {
+            println!("fail to persist deployment of circuit:{}, err:{}", meta.key, e);
+        }
+        Ok(registered.instance.info(&registered.meta))
+    }
+
+    fn find<'a>(versions: &'a BTreeMap<u32, Registered>, pin: &str) -> Option<&'a Registered> {
+        match pin.parse::<u32>() {
+            Ok(version) => versions.get(&version),
+            Err(_) => versions.values().find(|r| r.meta.r1cs_hash == pin),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Result<Registered, ZkpError> {
+        let (name, pin) = split_key(key);
+        let cache = self.mutex.read().unwrap();
+        let versions = cache.get(name).ok_or_else(|| ZkpError::UnknownCircuit(key.to_string()))?;
+        let found = match pin {
+            Some(pin) => Self::find(versions, pin),
+            None => versions.values().next_back(),
+        };
+        found.cloned().ok_or_else(|| ZkpError::UnknownCircuit(key.to_string()))
+    }
+
+    /// Instance for `key`, which may pin a version as `key@version` or `key@<r1cs hash>`.
+    pub fn get(&self, key: &str) -> Result<Arc<dyn ZKComponent>, ZkpError> {
+        self.lookup(key).map(|registered| registered.instance)
+    }
+    pub fn circuit_info(&self, key: &str) -> Result<CircuitInfo, ZkpError> {
+        self.lookup(key).map(|registered| registered.instance.info(&registered.meta))
+    }
+    /// Every registered version, sorted by key then version.
+    pub fn list_circuits(&self) -> Vec<CircuitInfo> {
         let cache = self.mutex.read().unwrap();
-        if let Some(instance) = cache.get(req.key.as_str()) {
-            let v = instance.lock().unwrap();
-            v.verify(req.clone())
-        } else {
-            Err(Error::new(ErrorKind::InvalidData, TempError {}))
+        let mut infos: Vec<CircuitInfo> = cache.values()
+            .flat_map(|versions| versions.values())
+            .map(|registered| registered.instance.info(&registered.meta))
+            .collect();
+        infos.sort_by(|a, b| a.key.cmp(&b.key).then(a.version.cmp(&b.version)));
+        infos
+    }
+    pub fn calculate_witness(&self, key: &str, inputs: &str) -> Result<Vec<u8>, ZkpError> {
+        self.get(key)?.calculate_witness(inputs)
+    }
+    pub fn check_witness(&self, key: &str, wtns: Vec<u8>) -> Result<(), ZkpError> {
+        self.get(key)?.check_witness(wtns)
+    }
+    pub fn verification_key(&self, key: &str) -> Result<VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>, ZkpError> {
+        self.get(key).map(|instance| instance.verification_key())
+    }
+    pub fn get_vk(&self, key: &str) -> Option<Vec<u8>> {
+        self.get(key).ok().and_then(|instance| instance.get_vk_and_sol().ok()).map(|(vk, _)| vk)
+    }
+    pub fn prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError> {
+        self.get(req.key.as_str())?.prove(req)
+    }
+    pub fn verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError> {
+        self.get(req.key.as_str())?.verify(req)
+    }
+    pub async fn async_prove(&self, req: ProveRequest) -> Result<ProveResponse, ZkpError> {
+        let instance = self.get(req.key.as_str())?;
+        instance.async_prove(req).await
+    }
+    pub async fn async_verify(&self, req: VerifyRequest) -> Result<VerifyResponse, ZkpError> {
+        let instance = self.get(req.key.as_str())?;
+        instance.async_verify(req).await
+    }
+    pub async fn async_prove_batch(&self, key: &str, witnesses: Vec<Vec<u8>>) -> Result<Vec<Result<ProveResponse, ZkpError>>, ZkpError> {
+        let instance = self.get(key)?;
+        let reqs = witnesses.into_iter().map(|wtns| ProveRequest { key: key.to_string(), wtns }).collect();
+        Ok(instance.async_prove_batch(reqs).await)
+    }
+    pub async fn async_verify_batch(&self, key: &str, reqs: Vec<VerifyRequest>) -> Result<Vec<Result<VerifyResponse, ZkpError>>, ZkpError> {
+        let instance = self.get(key)?;
+        Ok(instance.async_verify_batch(reqs).await)
+    }
+    pub fn submit_prove_job(&self, req: ProveRequest) -> Result<JobView, ZkpError> {
+        let instance = self.get(req.key.as_str())?;
+        let ticket = self.jobs.create(req.key.clone());
+        if let Err(e) = instance.submit_job(req, ticket.clone()) {
+            self.jobs.remove(ticket.id.as_str());
+            return Err(e);
         }
+        Ok(ticket.view())
+    }
+    pub fn job_status(&self, id: &str) -> Result<JobView, ZkpError> {
+        self.jobs.status(id)
+    }
+    pub fn cancel_job(&self, id: &str) -> Result<JobView, ZkpError> {
+        self.jobs.cancel(id)
     }
 }
 
+/// A proof in the `json_proof` / `inputs_json` layout returned by prove, i.e. what the on-chain verifier receives.
 pub struct PrettyVerifyRequest {
     pub key: String,
     pub proof: String,
+    pub inputs: String,
 }
 
-impl Into<VerifyRequest> for PrettyVerifyRequest {
-    fn into(self) -> VerifyRequest {
-        let vv: Vec<U256> = serde_json::from_str(self.proof.as_str()).unwrap();
-        VerifyRequest { key: self.key, proof_bytes: vec![] }
+impl TryFrom<PrettyVerifyRequest> for VerifyRequest {
+    type Error = ZkpError;
+
+    fn try_from(req: PrettyVerifyRequest) -> Result<Self, Self::Error> {
+        let serialized_proof: Vec<U256> = serde_json::from_str(req.proof.as_str()).map_err(|e| {
+            ZkpError::InvalidProof(e.to_string())
+        })?;
+        let inputs: Vec<U256> = serde_json::from_str(req.inputs.as_str()).map_err(|e| {
+            ZkpError::InvalidInputs(e.to_string())
+        })?;
+        Ok(VerifyRequest { key: req.key, proof_bytes: vec![], inputs: Some(inputs), serialized_proof: Some(serialized_proof) })
     }
 }
 
@@ -401,22 +961,71 @@ impl Into<VerifyRequest> for PrettyVerifyRequest {
 pub struct VerifyRequest {
     pub key: String,
     pub proof_bytes: Vec<u8>,
+    /// Expected public inputs, the proof is rejected when its own inputs differ.
+    #[serde(default)]
+    pub inputs: Option<Vec<U256>>,
+    /// Solidity layout proof, used instead of `proof_bytes` when set; requires `inputs`.
+    #[serde(default)]
+    pub serialized_proof: Option<Vec<U256>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct VerifyResponse {
     pub verify: bool,
 }
 
+/// What to do when the key is already registered with a different r1cs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterMode {
+    /// fail with `CircuitExists`
+    Reject,
+    /// replace the circuit, keeping its version
+    Overwrite,
+    /// replace the circuit under the next version
+    NewVersion,
+}
+
+impl Default for RegisterMode {
+    fn default() -> Self {
+        RegisterMode::Reject
+    }
+}
+
+impl FromStr for RegisterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(RegisterMode::Reject),
+            "overwrite" => Ok(RegisterMode::Overwrite),
+            "version" => Ok(RegisterMode::NewVersion),
+            _ => Err(format!("unknown register mode {}, expected reject, overwrite or version", s)),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct RegisterRequest {
     pub key: String,
     pub reader: Vec<u8>,
+    /// optional circom witness generator, enables proving from named inputs
+    pub wasm: Option<Vec<u8>>,
+    pub mode: RegisterMode,
 }
 
 impl RegisterRequest {
     pub fn new(key: String, reader: Vec<u8>) -> Self {
-        Self { key, reader }
+        Self { key, reader, wasm: None, mode: RegisterMode::default() }
+    }
+
+    pub fn with_mode(mut self, mode: RegisterMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_wasm(mut self, wasm: Vec<u8>) -> Self {
+        self.wasm = Some(wasm);
+        self
     }
 }
 
@@ -424,6 +1033,9 @@ impl RegisterRequest {
 pub struct RegisterResponse {
     pub vk: Vec<u8>,
     pub sol: String,
+    pub version: u32,
+    /// hex keccak256 of the registered r1cs
+    pub hash: String,
 }
 
 
@@ -433,7 +1045,7 @@ pub struct ProveRequest {
     pub wtns: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProveResponse {
     pub proof: Vec<u8>,
     pub hex_proof: String,
@@ -454,7 +1066,7 @@ fn register_simple() -> ZKPProverContainer {
     let mut reader = BufReader::new(reader);
     let mut buffer = Vec::<u8>::new();
     reader.read_to_end(&mut buffer).expect("fail");
-    container.register(RegisterRequest::new(String::from("demo"), buffer));
+    container.register(RegisterRequest::new(String::from("demo"), buffer)).expect("fail to register");
     container
 }
 
@@ -475,6 +1087,325 @@ pub fn test_verify() {
     let key = String::from("demo");
     let res = container.prove(ProveRequest { key: key.clone(), wtns }).expect("fail to prove");
     println!("{:?}", res);
-    let v = container.verify(VerifyRequest { key: key.clone(), proof_bytes: res.proof }).expect("fail to verify");
+    let v = container.verify(VerifyRequest { key: key.clone(), proof_bytes: res.proof, inputs: None, serialized_proof: None }).expect("fail to verify");
+    assert!(v.verify);
+}
+
+#[test]
+pub fn test_verify_with_inputs() {
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let wtns = fs::read(wit_file).expect("fail");
+    let key = String::from("demo");
+    let res = container.prove(ProveRequest { key: key.clone(), wtns }).expect("fail to prove");
+    let v = container.verify(VerifyRequest { key: key.clone(), proof_bytes: res.proof.clone(), inputs: Some(res.inputs.clone()), serialized_proof: None }).expect("fail to verify");
+    assert!(v.verify);
+
+    let mut wrong_inputs = res.inputs.clone();
+    wrong_inputs[0] = wrong_inputs[0] + U256::from(1);
+    let v = container.verify(VerifyRequest { key: key.clone(), proof_bytes: res.proof, inputs: Some(wrong_inputs), serialized_proof: None }).expect("fail to verify");
+    assert!(!v.verify);
+}
+
+#[test]
+pub fn test_prove_unknown_circuit() {
+    let container = ZKPProverContainer::default();
+    let res = container.prove(ProveRequest { key: String::from("missing"), wtns: vec![] });
+    assert!(matches!(res, Err(ZkpError::UnknownCircuit(_))));
+}
+
+#[test]
+pub fn test_verify_pretty_proof() {
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let wtns = fs::read(wit_file).expect("fail");
+    let key = String::from("demo");
+    let res = container.prove(ProveRequest { key: key.clone(), wtns }).expect("fail to prove");
+    let req = PrettyVerifyRequest { key: key.clone(), proof: res.json_proof, inputs: res.inputs_json };
+    let v = container.verify(req.try_into().expect("fail to parse")).expect("fail to verify");
     assert!(v.verify);
 }
+
+#[test]
+pub fn test_concurrent_prove_different_keys() {
+    use std::time::Duration;
+    use std::sync::atomic::AtomicBool;
+
+    let pool = Arc::new(ProvingPool::new(2));
+    let factory = ZKPFactory::new(Arc::new(SrsManager::default()), Arc::new(SetupKeyCatalog::scan(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup")).unwrap()), pool.clone());
+    let container = Arc::new(ZKPProverContainer::with_store(Arc::new(MemoryCircuitStore::default()), factory));
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let keys = vec!["a", "b", "c"];
+    for key in keys.iter() {
+        container.register(RegisterRequest::new(key.to_string(), r1cs.clone())).expect("fail to register");
+    }
+
+    // a long proof of some other circuit holds one of the two pool threads for the whole test
+    let (release, blocked) = crossbeam::channel::bounded::<()>(0);
+    let finished = Arc::new(AtomicBool::new(false));
+    let blocker_finished = finished.clone();
+    pool.execute(move || {
+        let _ = blocked.recv();
+        blocker_finished.store(true, Ordering::SeqCst);
+    }).expect("fail to block the pool");
+
+    let (results, proofs) = crossbeam::channel::unbounded();
+    for key in keys.iter() {
+        let container = container.clone();
+        let results = results.clone();
+        let req = ProveRequest { key: key.to_string(), wtns: wtns.clone() };
+        thread::spawn(move || results.send(container.prove(req)).unwrap());
+    }
+    for _ in keys.iter() {
+        let res = proofs.recv_timeout(Duration::from_secs(120)).expect("a busy pool thread held up the other keys");
+        res.expect("fail to prove");
+    }
+    assert!(!finished.load(Ordering::SeqCst));
+    release.send(()).expect("blocker is gone");
+}
+
+#[test]
+pub fn test_stopped_worker() {
+    use std::time::Duration;
+
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let instance = ZKPFactory::default().build_and_start(String::from("demo"), r1cs, None).expect("fail to build");
+    instance.shutdown();
+    thread::sleep(Duration::from_millis(200));
+    // the returned instance doesn't keep the channel open, so nothing waits on a worker that is gone
+    assert!(matches!(instance.prove(ProveRequest { key: String::from("demo"), wtns }), Err(ZkpError::WorkerUnavailable(_))));
+}
+
+#[test]
+pub fn test_prove_job() {
+    use std::time::Duration;
+    use crate::jobs::JobStatus;
+
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let wtns = fs::read(wit_file).expect("fail");
+    let job = container.submit_prove_job(ProveRequest { key: String::from("demo"), wtns }).expect("fail to submit");
+    loop {
+        match container.job_status(job.id.as_str()).expect("job missing").status {
+            JobStatus::Queued | JobStatus::Running => thread::sleep(Duration::from_millis(100)),
+            JobStatus::Succeeded { result } => {
+                assert!(!result.proof.is_empty());
+                break;
+            }
+            other => panic!("unexpected job status {:?}", other),
+        }
+    }
+    assert!(matches!(container.job_status("missing"), Err(ZkpError::UnknownJob(_))));
+}
+
+#[test]
+pub fn test_prove_batch() {
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let wtns = fs::read(wit_file).expect("fail");
+    let witnesses = vec![wtns.clone(), vec![1, 2, 3], wtns];
+    let res = futures::executor::block_on(container.async_prove_batch("demo", witnesses)).expect("fail to prove batch");
+    assert_eq!(res.len(), 3);
+    assert!(res[0].is_ok());
+    assert!(res[1].is_err());
+    assert!(res[2].is_ok());
+}
+
+#[test]
+pub fn test_verify_batch() {
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let wtns = fs::read(wit_file).expect("fail");
+    let key = String::from("demo");
+    let res = container.prove(ProveRequest { key: key.clone(), wtns }).expect("fail to prove");
+    let valid = VerifyRequest { key: key.clone(), proof_bytes: res.proof.clone(), inputs: None, serialized_proof: None };
+    let mut wrong_inputs = res.inputs.clone();
+    wrong_inputs[0] = wrong_inputs[0] + U256::from(1);
+    let invalid = VerifyRequest { key: key.clone(), proof_bytes: res.proof.clone(), inputs: Some(wrong_inputs), serialized_proof: None };
+
+    let results = futures::executor::block_on(container.async_verify_batch("demo", vec![valid.clone(), valid.clone()])).expect("fail to verify");
+    assert!(results.iter().all(|r| r.as_ref().unwrap().verify));
+    let results = futures::executor::block_on(container.async_verify_batch("demo", vec![valid.clone(), invalid, valid])).expect("fail to verify");
+    let verified: Vec<bool> = results.into_iter().map(|r| r.unwrap().verify).collect();
+    assert_eq!(verified, vec![true, false, true]);
+}
+
+#[test]
+pub fn test_prove_from_inputs() {
+    let container = ZKPProverContainer::default();
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wasm = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.wasm")).expect("fail");
+    container.register(RegisterRequest::new(String::from("demo"), r1cs).with_wasm(wasm)).expect("fail to register");
+
+    let wtns = container.calculate_witness("demo", r#"{"a": "1", "b": "2"}"#).expect("fail to calculate witness");
+    let res = container.prove(ProveRequest { key: String::from("demo"), wtns }).expect("fail to prove");
+    let v = container.verify(VerifyRequest { key: String::from("demo"), proof_bytes: res.proof, inputs: None, serialized_proof: None }).expect("fail to verify");
+    assert!(v.verify);
+
+    let container = register_simple();
+    assert!(matches!(container.calculate_witness("demo", r#"{"a": "1", "b": "2"}"#), Err(ZkpError::NoWitnessCalculator(_))));
+}
+
+#[test]
+pub fn test_prove_unsatisfied_witness() {
+    let container = register_simple();
+    let wit_file = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns");
+    let mut wtns = fs::read(wit_file).expect("fail");
+    container.check_witness("demo", wtns.clone()).expect("valid witness rejected");
+
+    // lowest byte of the second value, the circuit output
+    wtns[76 + 32] += 1;
+    let res = container.prove(ProveRequest { key: String::from("demo"), wtns: wtns.clone() });
+    assert!(matches!(res, Err(ZkpError::UnsatisfiedConstraint { index: 0 })));
+    assert!(matches!(container.check_witness("demo", wtns), Err(ZkpError::UnsatisfiedConstraint { index: 0 })));
+}
+
+#[test]
+pub fn test_circuit_info() {
+    let container = register_simple();
+    let info = container.circuit_info("demo").expect("fail to get info");
+    assert_eq!(info.num_constraints, 1);
+    assert_eq!(info.num_variables, 4);
+    assert_eq!((info.num_public_inputs, info.num_outputs, info.num_private_inputs), (0, 1, 2));
+    assert!(info.setup_power >= srs::required_power(info.domain_size));
+    assert!(!info.witness_generator);
+    assert_eq!(container.list_circuits().len(), 1);
+    assert!(matches!(container.circuit_info("missing"), Err(ZkpError::UnknownCircuit(_))));
+}
+
+#[test]
+pub fn test_register_modes() {
+    let container = register_simple();
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    // same constraints with another label for the last wire, so only the content hash differs
+    let relabel = |label: u8| {
+        let mut other = r1cs.clone();
+        let last_label = other.len() - 8;
+        other[last_label] = label;
+        other
+    };
+
+    // same r1cs is a no-op whatever the mode
+    let first = container.register(RegisterRequest::new(String::from("demo"), r1cs.clone())).expect("fail to register");
+    assert_eq!(first.version, 1);
+    let res = container.register(RegisterRequest::new(String::from("demo"), relabel(4)));
+    assert!(matches!(res, Err(ZkpError::CircuitExists(_))));
+
+    let res = container.register(RegisterRequest::new(String::from("demo"), relabel(4)).with_mode(RegisterMode::NewVersion)).expect("fail to register");
+    assert_eq!(res.version, 2);
+    let res = container.register(RegisterRequest::new(String::from("demo"), relabel(5)).with_mode(RegisterMode::Overwrite)).expect("fail to register");
+    assert_eq!(res.version, 2);
+    assert_eq!(container.circuit_info("demo").unwrap().hash, res.hash);
+    // registering the first r1cs again finds version 1
+    let res = container.register(RegisterRequest::new(String::from("demo"), r1cs).with_mode(RegisterMode::NewVersion)).expect("fail to register");
+    assert_eq!((res.version, res.hash.as_str()), (1, first.hash.as_str()));
+    container.prove(ProveRequest { key: String::from("demo"), wtns: wtns.clone() }).expect("fail to prove");
+
+    let removed = container.deregister("demo").expect("fail to deregister");
+    assert_eq!(removed.iter().map(|info| info.version).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(matches!(container.prove(ProveRequest { key: String::from("demo"), wtns }), Err(ZkpError::UnknownCircuit(_))));
+    assert!(matches!(container.deregister("demo"), Err(ZkpError::UnknownCircuit(_))));
+}
+
+#[test]
+pub fn test_pinned_versions() {
+    let container = register_simple();
+    let mut r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let first = container.circuit_info("demo").unwrap();
+    let last_label = r1cs.len() - 8;
+    r1cs[last_label] = 4;
+    let second = container.register(RegisterRequest::new(String::from("demo"), r1cs).with_mode(RegisterMode::NewVersion)).expect("fail to register");
+
+    assert_eq!(container.circuit_info("demo").unwrap().version, 2);
+    assert_eq!(container.circuit_info("demo@1").unwrap().version, 1);
+    assert_eq!(container.circuit_info(format!("demo@{}", second.hash).as_str()).unwrap().version, 2);
+    assert!(matches!(container.circuit_info("demo@3"), Err(ZkpError::UnknownCircuit(_))));
+    let pinned = format!("demo@{}", first.hash);
+    let res = container.prove(ProveRequest { key: pinned.clone(), wtns }).expect("fail to prove");
+    let v = container.verify(VerifyRequest { key: pinned, proof_bytes: res.proof, inputs: None, serialized_proof: None }).expect("fail to verify");
+    assert!(v.verify);
+
+    container.deregister("demo@2").expect("fail to deregister");
+    assert_eq!(container.circuit_info("demo").unwrap().version, 1);
+    assert_eq!(container.list_circuits().len(), 1);
+    assert!(matches!(container.register(RegisterRequest::new(String::from("demo@1"), vec![])), Err(ZkpError::InvalidCircuitKey(_))));
+    assert!(matches!(container.register(RegisterRequest::new(String::from("demo\0"), vec![])), Err(ZkpError::InvalidCircuitKey(_))));
+    assert!(matches!(container.register(RegisterRequest::new("k".repeat(store::MAX_KEY_LEN + 1), vec![])), Err(ZkpError::InvalidCircuitKey(_))));
+}
+
+#[test]
+pub fn test_verify_with_vk() {
+    let container = register_simple();
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let res = container.prove(ProveRequest { key: String::from("demo"), wtns }).expect("fail to prove");
+    let vk_bytes = container.get_vk("demo").expect("no vk");
+    drop(container);
+
+    // nothing registered anymore, the vk bytes are all there is
+    let vk = crate::vk::from_bytes(vk_bytes.as_slice()).expect("fail to read vk");
+    let mut req = VerifyRequest { key: String::new(), proof_bytes: res.proof, inputs: Some(res.inputs.clone()), serialized_proof: None };
+    assert!(verify_with_vk(&vk, &req).expect("fail to verify").verify);
+    req.inputs = Some(vec![res.inputs[0] + U256::from(1)]);
+    assert!(!verify_with_vk(&vk, &req).expect("fail to verify").verify);
+    assert!(crate::vk::from_bytes(&vk_bytes[..10]).is_err());
+}
+
+#[test]
+pub fn test_record_deployment() {
+    let container = register_simple();
+    let meta = container.lookup("demo").expect("not registered").meta;
+    let deployment = Deployment { address: ethers::types::Address::repeat_byte(1), chain_id: 31337, tx_hash: Default::default(), block_number: Some(1), deployed_at: 0 };
+    let info = container.record_deployment(&meta, deployment.clone()).expect("fail to record");
+    assert_eq!(info.deployment, Some(deployment.clone()));
+    assert_eq!(container.circuit_info("demo@1").unwrap().deployment, Some(deployment.clone()));
+
+    // the deployed r1cs was replaced in the meantime
+    let mut stale = meta.clone();
+    stale.r1cs_hash = String::from("00");
+    assert!(matches!(container.record_deployment(&stale, deployment), Err(ZkpError::UnknownCircuit(_))));
+}
+
+#[test]
+pub fn test_solidity_args() {
+    let container = register_simple();
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let res = container.prove(ProveRequest { key: String::from("demo"), wtns }).expect("fail to prove");
+    let serialized_proof: Vec<U256> = serde_json::from_str(res.json_proof.as_str()).unwrap();
+
+    // raw proof bytes and the json layout end up as the same contract arguments
+    let req = VerifyRequest { key: String::from("demo"), proof_bytes: res.proof.clone(), inputs: None, serialized_proof: None };
+    assert_eq!(solidity_args(&req).expect("fail to serialize"), (res.inputs.clone(), serialized_proof.clone()));
+    let req: VerifyRequest = PrettyVerifyRequest { key: String::from("demo"), proof: res.json_proof, inputs: res.inputs_json }.try_into().unwrap();
+    assert_eq!(solidity_args(&req).expect("fail to serialize"), (res.inputs.clone(), serialized_proof));
+
+    let wrong_inputs = vec![res.inputs[0] + U256::from(1)];
+    let req = VerifyRequest { key: String::from("demo"), proof_bytes: res.proof, inputs: Some(wrong_inputs.clone()), serialized_proof: None };
+    assert_eq!(solidity_args(&req).unwrap().0, wrong_inputs);
+
+    let verifier = OnChainVerifier::new("http://127.0.0.1:8545").unwrap();
+    let res = futures::executor::block_on(container.verify_onchain(req, &verifier, false));
+    assert!(matches!(res, Err(ZkpError::NotDeployed(_))));
+}
+
+#[test]
+pub fn test_verify_onchain_other_chain() {
+    use ethers::providers::{Http, Provider};
+    use ethers::types::{Address, H256};
+
+    let container = register_simple();
+    let meta = container.lookup("demo").unwrap().meta;
+    container.record_deployment(&meta, Deployment { address: Address::zero(), chain_id: 1, tx_hash: H256::zero(), block_number: None, deployed_at: 0 }).expect("fail to record");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let res = container.prove(ProveRequest { key: String::from("demo"), wtns }).expect("fail to prove");
+
+    // the verifier talks to another chain than the one the circuit was deployed on, so nothing is asked
+    let wallet = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".parse().unwrap();
+    let verifier = OnChainVerifier::with_signer(Provider::<Http>::try_from("http://127.0.0.1:1").unwrap(), wallet, Some(31337));
+    let req = VerifyRequest { key: String::from("demo"), proof_bytes: res.proof, inputs: None, serialized_proof: None };
+    let res = futures::executor::block_on(container.verify_onchain(req, &verifier, false));
+    assert!(matches!(res, Err(ZkpError::NotDeployed(_))));
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::errors::ZkpError;
+use crate::instance::{ResultOperation, ProveResponse};
+
+// finished jobs are kept around this long so clients can still poll the result
+const JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded { result: ProveResponse },
+    Failed { code: String, message: String },
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobView {
+    pub id: String,
+    pub key: String,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+/// Shared between the job table and the `Cmd` travelling through the circuit worker,
+/// the worker moves it along queued -> running -> succeeded/failed.
+pub struct JobTicket {
+    pub id: String,
+    pub key: String,
+    status: Mutex<JobStatus>,
+    finished_at: Mutex<Option<Instant>>,
+}
+
+impl JobTicket {
+    fn new(id: String, key: String) -> Self {
+        Self { id, key, status: Mutex::new(JobStatus::Queued), finished_at: Mutex::new(None) }
+    }
+
+    /// Marks the job running, false when it was cancelled while queued and must be skipped.
+    pub fn start(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if let JobStatus::Queued = *status {
+            *status = JobStatus::Running;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn finish(&self, result: &ResultOperation) {
+        let status = match result {
+            ResultOperation::Proof(resp) => JobStatus::Succeeded { result: resp.clone() },
+            ResultOperation::Fail(e) => JobStatus::Failed { code: e.code().to_string(), message: e.to_string() },
+            _ => unreachable!(),
+        };
+        self.set_finished(status);
+    }
+
+    /// Only queued jobs can be cancelled, a running proof can't be interrupted.
+    pub fn cancel(&self) -> bool {
+        // checked and changed under one lock, so a worker starting the job in between can't be overwritten
+        let mut status = self.status.lock().unwrap();
+        if !matches!(*status, JobStatus::Queued) {
+            return false;
+        }
+        *status = JobStatus::Cancelled;
+        *self.finished_at.lock().unwrap() = Some(Instant::now());
+        true
+    }
+
+    pub fn view(&self) -> JobView {
+        JobView { id: self.id.clone(), key: self.key.clone(), status: self.status.lock().unwrap().clone() }
+    }
+
+    fn set_finished(&self, status: JobStatus) {
+        *self.status.lock().unwrap() = status;
+        *self.finished_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn expired(&self) -> bool {
+        self.finished_at.lock().unwrap().map(|t| t.elapsed() > JOB_RETENTION).unwrap_or(false)
+    }
+}
+
+#[derive(Default)]
+pub struct JobManager {
+    jobs: RwLock<HashMap<String, Arc<JobTicket>>>,
+    next_id: AtomicU64,
+}
+
+impl JobManager {
+    pub fn create(&self, key: String) -> Arc<JobTicket> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+        let id = format!("{:x}-{:x}", millis, self.next_id.fetch_add(1, Ordering::SeqCst));
+        let ticket = Arc::new(JobTicket::new(id.clone(), key));
+        let mut jobs = self.jobs.write().unwrap();
+        jobs.retain(|_, job| !job.expired());
+        jobs.insert(id, ticket.clone());
+        ticket
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.jobs.write().unwrap().remove(id);
+    }
+
+    pub fn status(&self, id: &str) -> Result<JobView, ZkpError> {
+        self.get(id).map(|job| job.view())
+    }
+
+    pub fn cancel(&self, id: &str) -> Result<JobView, ZkpError> {
+        let job = self.get(id)?;
+        if !job.cancel() && !job.status.lock().unwrap().is_finished() {
+            return Err(ZkpError::JobNotCancellable(id.to_string()));
+        }
+        Ok(job.view())
+    }
+
+    fn get(&self, id: &str) -> Result<Arc<JobTicket>, ZkpError> {
+        self.jobs.read().unwrap().get(id).cloned().ok_or_else(|| ZkpError::UnknownJob(id.to_string()))
+    }
+}
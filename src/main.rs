@@ -5,15 +5,29 @@ extern crate rocket;
 
 use std::fs::OpenOptions;
 use std::io::Cursor;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::Arc;
 use ethers::utils::hex;
 use lazy_static::lazy_static;
+use primitive_types::U256;
 use rocket::form::{Context, Contextual, Form};
 use rocket::fs::FileServer;
 use rocket::http::{ContentType, Status};
-use rocket::{Data, routes};
-use rocket_multipart_form_data::{MultipartFormData, MultipartFormDataField, MultipartFormDataOptions};
-use crate::instance::{ProveRequest, RegisterRequest, VerifyRequest, ZKPProverContainer};
+use rocket::{Build, Data, Rocket, State, routes};
+use clap::Parser;
+use crate::cli::{Cli, Command};
+use rocket::serde::json::{json, Value};
+use rocket_multipart_form_data::{MultipartFormData, MultipartFormDataField};
+use crate::api::{ApiError, BatchItem, VkResponse};
+use crate::vk::VkFormat;
+use crate::errors::ZkpError;
+use crate::instance::{PrettyVerifyRequest, ProveRequest, ProveResponse, RegisterMode, RegisterRequest, SETUP_KEY_DIR, VerifyRequest, VerifyResponse, ZKPFactory, ZKPProverContainer};
+use crate::store::DirCircuitStore;
+use crate::srs::{SetupKeyCatalog, SrsManager};
+use crate::pool::ProvingPool;
+use crate::contract_adapter::{Deployer, DeployerConfig};
+use crate::verifier_contract::OnChainVerifier;
+use crate::anvil::LocalNode;
 
 
 mod events;
@@ -22,102 +36,311 @@ mod contract_adapter;
 pub mod verifier_contract;
 pub mod circuits;
 mod instance;
+mod store;
+mod errors;
+mod api;
+mod srs;
+mod serialization;
+mod pool;
+mod jobs;
+mod batch_verify;
+mod witness;
+mod info;
+mod vk;
+mod cli;
+#[cfg(test)]
+mod consistency;
+
+// relative to the working directory, not to wherever the binary was built
+const DEFAULT_DATA_DIR: &'static str = "data";
 
 lazy_static! {
-    static ref ZKPInstance: Arc<Mutex<ZKPProverContainer>> = init_zkp();
+    static ref ZKPInstance: Arc<ZKPProverContainer> = init_zkp();
 }
 
-fn init_zkp() -> Arc<Mutex<ZKPProverContainer>> {
-    let zkp = ZKPProverContainer::default();
-    Arc::new(Mutex::new(zkp))
+fn init_zkp() -> Arc<ZKPProverContainer> {
+    // ZKP_DATA_DIR overrides where registered circuits are persisted
+    let data_dir = std::env::var("ZKP_DATA_DIR").unwrap_or(String::from(DEFAULT_DATA_DIR));
+    let store = DirCircuitStore::new(data_dir.clone()).expect("fail to open data dir");
+    let srs = SrsManager::new(Some(PathBuf::from(data_dir).join("srs"))).expect("fail to open srs cache dir");
+    // ZKP_SETUP_DIR overrides where setup_2^N.key files are looked up
+    let setup_dir = std::env::var("ZKP_SETUP_DIR").unwrap_or(String::from(SETUP_KEY_DIR));
+    let catalog = SetupKeyCatalog::scan(setup_dir).expect("fail to scan setup dir");
+    let zkp = ZKPProverContainer::with_store(Arc::new(store), ZKPFactory::new(Arc::new(srs), Arc::new(catalog), Arc::new(ProvingPool::default())));
+    Arc::new(zkp)
 }
 
+fn init_deployer(node: Option<&LocalNode>) -> Option<Deployer> {
+    // an explicit deployer wins over the local node's first wallet
+    let config = match (DeployerConfig::from_env().expect("invalid deployer config"), node) {
+        (Some(config), _) => Some(config),
+        (None, Some(node)) => Some(node.deployer_config().expect("invalid deployer config")),
+        (None, None) => None,
+    };
+    match config {
+        Some(config) => Some(Deployer::new(config).expect("invalid deployer config")),
+        None => {
+            println!("neither ZKP_DEPLOYER_KEY nor ZKP_LOCAL_NODE is set, verifier deployment is disabled");
+            None
+        }
+    }
+}
 
-#[post("/", data = "<data>")]
-async fn register<'r>(content_type: &ContentType, data: Data<'_>) -> String {
-    let mut options = MultipartFormDataOptions::with_multipart_form_data_fields(
-        vec![
-            MultipartFormDataField::raw("r1cs").size_limit(1024 * 1024 * 1024),
-            MultipartFormDataField::text("key"),
-        ]
-    );
-    let mut multipart_form_data_res = MultipartFormData::parse(content_type, data, options).await;
-    if let Err(e) = multipart_form_data_res {
-        return e.to_string();
+fn init_onchain_verifier(deployer: Option<&Deployer>, node: Option<&LocalNode>) -> OnChainVerifier {
+    // transactions are sent as the deployer, plain calls need no signer
+    match deployer {
+        Some(deployer) => deployer.on_chain_verifier(),
+        None => {
+            let rpc_url = node.map(|node| node.http_url().to_string()).unwrap_or_else(contract_adapter::rpc_url_from_env);
+            OnChainVerifier::new(rpc_url.as_str()).expect("invalid rpc url")
+        }
     }
-    let mut multipart_form_data = multipart_form_data_res.unwrap();
-    let r1cs_field = multipart_form_data.raw.get_mut("r1cs").unwrap().remove(0); //
-    let key_field = multipart_form_data.texts.get_mut("key").unwrap().remove(0).text;
+}
+
+#[post("/", data = "<data>")]
+async fn register<'r>(content_type: &ContentType, data: Data<'_>) -> Result<Value, ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        MultipartFormDataField::raw("r1cs").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::raw("wasm").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("key"),
+        MultipartFormDataField::text("mode"),
+    ]).await?;
+    let r1cs_field = api::take_raw(&mut multipart_form_data, "r1cs")?;
+    let wasm_field = api::take_optional_raw(&mut multipart_form_data, "wasm");
+    let key_field = api::take_text(&mut multipart_form_data, "key")?;
+    let mode = match api::take_optional_text(&mut multipart_form_data, "mode") {
+        Some(mode) => mode.parse::<RegisterMode>().map_err(|e| ApiError::bad_request("invalid_mode", e))?,
+        None => RegisterMode::default(),
+    };
     println!("key:{}", key_field);
 
-    let mut binding = ZKPInstance.clone();
-    let mut vv = binding.lock().unwrap();
-    let req = RegisterRequest { key: key_field, reader: r1cs_field.raw };
-    let resp = vv.register(req);
-    serde_json::json!(resp).to_string()
+    let req = RegisterRequest { key: key_field, reader: r1cs_field, wasm: wasm_field, mode };
+    // building the setup is cpu bound, keep it off the async workers
+    let resp = rocket::tokio::task::spawn_blocking(move || ZKPInstance.register(req)).await.map_err(|e| {
+        ZkpError::WorkerUnavailable(e.to_string())
+    })??;
+    Ok(json!(resp))
 }
 
 #[post("/", data = "<data>")]
-async fn prove<'r>(content_type: &ContentType, data: Data<'_>) -> String {
-    let mut options = MultipartFormDataOptions::with_multipart_form_data_fields(
-        vec![
-            MultipartFormDataField::raw("witness").size_limit(1024 * 1024 * 1024),
-            MultipartFormDataField::text("key"),
-        ]
-    );
-    let mut multipart_form_data_res = MultipartFormData::parse(content_type, data, options).await;
-    if let Err(e) = multipart_form_data_res {
-        return e.to_string();
-    }
-    let mut multipart_form_data = multipart_form_data_res.unwrap();
-    let file_field = multipart_form_data.raw.get_mut("witness").unwrap().remove(0); //
-    let key_field = multipart_form_data.texts.get_mut("key").unwrap().remove(0).text;
-
-    let mut binding = ZKPInstance.clone();
-    let mut vv = binding.lock().unwrap();
-    let req = ProveRequest { key: key_field, wtns: file_field.raw };
-    let resp = vv.prove(req);
-    if let Err(e) = resp {
-        return e.to_string();
+async fn prove<'r>(content_type: &ContentType, data: Data<'_>) -> Result<(Status, Value), ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        MultipartFormDataField::raw("witness").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("inputs").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("key"),
+        MultipartFormDataField::text("async"),
+    ]).await?;
+    let key_field = api::take_text(&mut multipart_form_data, "key")?;
+    let is_async = api::take_optional_text(&mut multipart_form_data, "async").map(|v| v == "true").unwrap_or(false);
+    // either a ready made .wtns file or named input signals for the circuit's witness generator
+    let file_field = match api::take_optional_text(&mut multipart_form_data, "inputs") {
+        Some(inputs) => {
+            let key = key_field.clone();
+            rocket::tokio::task::spawn_blocking(move || ZKPInstance.calculate_witness(key.as_str(), inputs.as_str())).await.map_err(|e| {
+                ZkpError::WorkerUnavailable(e.to_string())
+            })??
+        }
+        None => api::take_raw(&mut multipart_form_data, "witness")?,
+    };
+
+    let req = ProveRequest { key: key_field, wtns: file_field };
+    // async proofs answer with a job to poll on /jobs/<id>
+    if is_async {
+        let job = ZKPInstance.submit_prove_job(req)?;
+        return Ok((Status::Accepted, json!(job)));
     }
-    serde_json::json!(resp.unwrap()).to_string()
+    let resp = ZKPInstance.async_prove(req).await?;
+    Ok((Status::Ok, json!(resp)))
 }
 
-#[post("/", data = "<data>")]
-async fn verify<'r>(content_type: &ContentType, data: Data<'_>) -> String {
-    let mut options = MultipartFormDataOptions::with_multipart_form_data_fields(
-        vec![
-            MultipartFormDataField::text("hex_proof").size_limit(1024 * 1024 * 1024),
-            MultipartFormDataField::text("key").size_limit(1024 * 1024 * 1024),
-        ]
-    );
-    let mut multipart_form_data_res = MultipartFormData::parse(content_type, data, options).await;
-    if let Err(e) = multipart_form_data_res {
-        return e.to_string();
+#[post("/batch", data = "<data>")]
+async fn prove_batch<'r>(content_type: &ContentType, data: Data<'_>) -> Result<Value, ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        api::repeated(MultipartFormDataField::raw("witness").size_limit(1024 * 1024 * 1024)),
+        MultipartFormDataField::text("key"),
+    ]).await?;
+    let witnesses = api::take_all_raw(&mut multipart_form_data, "witness")?;
+    let key_field = api::take_text(&mut multipart_form_data, "key")?;
+
+    let results = ZKPInstance.async_prove_batch(key_field.as_str(), witnesses).await?;
+    let results: Vec<BatchItem<ProveResponse>> = results.into_iter().map(BatchItem::from).collect();
+    Ok(json!({ "results": results }))
+}
+
+#[get("/")]
+fn list_circuits() -> Value {
+    json!({ "circuits": ZKPInstance.list_circuits() })
+}
+
+#[get("/<key>")]
+fn circuit_info(key: &str) -> Result<Value, ApiError> {
+    let info = ZKPInstance.circuit_info(key)?;
+    Ok(json!(info))
+}
+
+#[get("/<key>/vk?<format>")]
+fn export_vk(key: &str, format: Option<&str>) -> Result<VkResponse, ApiError> {
+    let format = match format {
+        Some(format) => format.parse::<VkFormat>().map_err(|e| ApiError::bad_request("invalid_format", e))?,
+        None => VkFormat::Bin,
+    };
+    let vk = ZKPInstance.verification_key(key)?;
+    Ok(match format {
+        VkFormat::Bin => VkResponse::Bin(vk::to_bytes(&vk)?),
+        VkFormat::Json => VkResponse::Json(vk::to_json(&vk)),
+        VkFormat::Snarkjs => VkResponse::Json(vk::to_snarkjs(&vk)?),
+    })
+}
+
+#[delete("/<key>")]
+fn deregister(key: &str) -> Result<Value, ApiError> {
+    let removed = ZKPInstance.deregister(key)?;
+    Ok(json!({ "removed": removed }))
+}
+
+/// Compiles and deploys the circuit's solidity verifier, the contract address is kept with the circuit.
+#[post("/<key>/deploy")]
+async fn deploy(key: &str, deployer: &State<Option<Deployer>>) -> Result<Value, ApiError> {
+    let deployer = deployer.inner().as_ref().ok_or(ZkpError::DeployerNotConfigured)?;
+    let info = ZKPInstance.deploy(key, deployer).await?;
+    Ok(json!(info))
+}
+
+#[get("/<id>")]
+fn job_status(id: &str) -> Result<Value, ApiError> {
+    let job = ZKPInstance.job_status(id)?;
+    Ok(json!(job))
+}
+
+#[delete("/<id>")]
+fn cancel_job(id: &str) -> Result<Value, ApiError> {
+    let job = ZKPInstance.cancel_job(id)?;
+    Ok(json!(job))
+}
+
+#[post("/check", data = "<data>")]
+async fn check_witness<'r>(content_type: &ContentType, data: Data<'_>) -> Result<Value, ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        MultipartFormDataField::raw("witness").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("key"),
+    ]).await?;
+    let file_field = api::take_raw(&mut multipart_form_data, "witness")?;
+    let key_field = api::take_text(&mut multipart_form_data, "key")?;
+
+    let ret = rocket::tokio::task::spawn_blocking(move || ZKPInstance.check_witness(key_field.as_str(), file_field)).await.map_err(|e| {
+        ZkpError::WorkerUnavailable(e.to_string())
+    })?;
+    // an unsatisfied constraint is the answer to the check, not a failed request
+    match ret {
+        Ok(()) => Ok(json!({ "valid": true })),
+        Err(ZkpError::UnsatisfiedConstraint { index }) => Ok(json!({ "valid": false, "unsatisfied_constraint": index })),
+        Err(e) => Err(e.into()),
     }
-    let mut multipart_form_data = multipart_form_data_res.unwrap();
-    let hex_proof = multipart_form_data.texts.get_mut("hex_proof").unwrap().remove(0).text;
-    let key = multipart_form_data.texts.get_mut("key").unwrap().remove(0).text;
+}
 
+/// `mode=onchain` asks the circuit's deployed verifier contract instead, `transact=true` then also
+/// sends the proof in a transaction once the call accepted it.
+#[post("/?<mode>", data = "<data>")]
+async fn verify<'r>(mode: Option<&str>, content_type: &ContentType, data: Data<'_>, verifier: &State<OnChainVerifier>) -> Result<Value, ApiError> {
+    let onchain = match mode.unwrap_or("offchain") {
+        "offchain" => false,
+        "onchain" => true,
+        other => return Err(ApiError::bad_request("invalid_mode", format!("unknown verify mode {}, expected offchain or onchain", other))),
+    };
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        MultipartFormDataField::text("hex_proof").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("json_proof").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("key").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("inputs"),
+        MultipartFormDataField::text("transact"),
+    ]).await?;
+    let key = api::take_text(&mut multipart_form_data, "key")?;
+    let transact = api::take_optional_text(&mut multipart_form_data, "transact").map(|v| v == "true").unwrap_or(false);
+    let req = take_verify_request(&mut multipart_form_data, key)?;
 
-    let mut binding = ZKPInstance.clone();
-    let mut vv = binding.lock().unwrap();
-    let req = HexVerifyRequest { key: key, hex_proof: hex_proof };
-    let resp = vv.verify(req.into());
-    if let Err(e) = resp {
-        return e.to_string();
+    if onchain {
+        let resp = ZKPInstance.verify_onchain(req, verifier.inner(), transact).await?;
+        return Ok(json!(resp));
     }
-    serde_json::json!(resp.unwrap()).to_string()
+    let resp = ZKPInstance.async_verify(req).await?;
+    Ok(json!(resp))
+}
+
+/// Verifies against the uploaded vk (the bytes returned by /register) instead of a registered circuit.
+#[post("/with-vk", data = "<data>")]
+async fn verify_with_vk<'r>(content_type: &ContentType, data: Data<'_>) -> Result<Value, ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        MultipartFormDataField::raw("vk").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("hex_proof").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("json_proof").size_limit(1024 * 1024 * 1024),
+        MultipartFormDataField::text("inputs"),
+    ]).await?;
+    let vk = vk::from_bytes(api::take_raw(&mut multipart_form_data, "vk")?.as_slice())?;
+    let req = take_verify_request(&mut multipart_form_data, String::new())?;
+
+    let resp = rocket::tokio::task::spawn_blocking(move || instance::verify_with_vk(&vk, &req)).await.map_err(|e| {
+        ZkpError::WorkerUnavailable(e.to_string())
+    })??;
+    Ok(json!(resp))
+}
+
+/// Reads the proof fields shared by the verify endpoints: `hex_proof`, or `json_proof` with `inputs`.
+fn take_verify_request(form: &mut MultipartFormData, key: String) -> Result<VerifyRequest, ApiError> {
+    let inputs = api::take_optional_text(form, "inputs");
+    // json_proof is the solidity layout returned by /prove, it always comes with its public inputs
+    let req = match api::take_optional_text(form, "json_proof") {
+        Some(json_proof) => {
+            let inputs = inputs.ok_or_else(|| ApiError::bad_request("missing_field", "form field inputs is required with json_proof"))?;
+            PrettyVerifyRequest { key, proof: json_proof, inputs }.try_into()?
+        }
+        None => {
+            let hex_proof = api::take_text(form, "hex_proof")?;
+            HexVerifyRequest { key: key, hex_proof: hex_proof, inputs: inputs }.try_into()?
+        }
+    };
+    Ok(req)
+}
+
+#[post("/batch", data = "<data>")]
+async fn verify_batch<'r>(content_type: &ContentType, data: Data<'_>) -> Result<Value, ApiError> {
+    let mut multipart_form_data = api::parse_form(content_type, data, vec![
+        api::repeated(MultipartFormDataField::text("hex_proof").size_limit(1024 * 1024 * 1024)),
+        MultipartFormDataField::text("key"),
+    ]).await?;
+    let hex_proofs = api::take_all_text(&mut multipart_form_data, "hex_proof")?;
+    let key = api::take_text(&mut multipart_form_data, "key")?;
+    let reqs = hex_proofs.into_iter()
+        .map(|hex_proof| HexVerifyRequest { key: key.clone(), hex_proof, inputs: None }.try_into())
+        .collect::<Result<Vec<VerifyRequest>, ZkpError>>()?;
+
+    let results = ZKPInstance.async_verify_batch(key.as_str(), reqs).await?;
+    let results: Vec<BatchItem<VerifyResponse>> = results.into_iter().map(BatchItem::from).collect();
+    Ok(json!({ "results": results }))
 }
 
 pub struct HexVerifyRequest {
     pub key: String,
     pub hex_proof: String,
+    /// json array in the `inputs_json` format returned by /prove
+    pub inputs: Option<String>,
 }
 
-impl Into<VerifyRequest> for HexVerifyRequest {
-    fn into(self) -> VerifyRequest {
-        VerifyRequest { key: self.key, proof_bytes: hex::decode(self.hex_proof).unwrap() }
+impl TryFrom<HexVerifyRequest> for VerifyRequest {
+    type Error = ZkpError;
+
+    fn try_from(req: HexVerifyRequest) -> Result<Self, Self::Error> {
+        // the demo script pipes `jq .hex_proof` output, which keeps the json quotes
+        let hex_proof = req.hex_proof.trim().trim_matches('"');
+        let proof_bytes = hex::decode(hex_proof).map_err(|e| {
+            ZkpError::InvalidProof(e.to_string())
+        })?;
+        let inputs = match req.inputs {
+            Some(inputs) => Some(serde_json::from_str::<Vec<U256>>(inputs.as_str()).map_err(|e| {
+                ZkpError::InvalidInputs(e.to_string())
+            })?),
+            None => None,
+        };
+        Ok(VerifyRequest { key: req.key, proof_bytes, inputs, serialized_proof: None })
     }
 }
 
@@ -130,11 +353,44 @@ fn error_return(str: &'static str) -> String {
     String::from(str)
 }
 
-#[launch]
-fn rocket() -> _ {
+fn main() {
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            // restores the persisted circuits, launches the local node and reports a bad deployer config before serving
+            lazy_static::initialize(&ZKPInstance);
+            let node = LocalNode::from_env().expect("invalid local node config");
+            let deployer = init_deployer(node.as_ref());
+            let verifier = init_onchain_verifier(deployer.as_ref(), node.as_ref());
+            let ret = rocket::execute(rocket(deployer, verifier).launch());
+            // kills a launched anvil, exiting below would skip it
+            drop(node);
+            if let Err(e) = ret {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        command => match cli::run(command) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+fn rocket(deployer: Option<Deployer>, verifier: OnChainVerifier) -> Rocket<Build> {
     rocket::build()
+        .manage(deployer)
+        .manage(verifier)
         .mount("/register", routes![register])
-        .mount("/prove", routes![prove])
-        .mount("/verify", routes![verify])
+        .mount("/prove", routes![prove, prove_batch])
+        .mount("/verify", routes![verify, verify_batch, verify_with_vk])
+        .mount("/jobs", routes![job_status, cancel_job])
+        .mount("/witness", routes![check_witness])
+        .mount("/circuits", routes![list_circuits, circuit_info, export_vk, deregister, deploy])
         .mount("/test", routes!(test))
+        .register("/", catchers![api::default_catcher])
 }
\ No newline at end of file
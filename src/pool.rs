@@ -0,0 +1,52 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use crossbeam::channel::{Receiver, Sender};
+use crate::errors::ZkpError;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed set of threads shared by every registered circuit for the cpu heavy work,
+/// so the number of circuits doesn't decide how many cores are busy.
+/// Threads exit once the pool is dropped and the queued jobs are drained.
+pub struct ProvingPool {
+    sender: Sender<Job>,
+    size: usize,
+}
+
+impl Default for ProvingPool {
+    fn default() -> Self {
+        let size = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Self::new(size)
+    }
+}
+
+impl ProvingPool {
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (sender, receiver) = crossbeam::channel::unbounded::<Job>();
+        for i in 0..size {
+            let receiver: Receiver<Job> = receiver.clone();
+            thread::Builder::new()
+                .name(format!("zkp-prover-{}", i))
+                .spawn(move || {
+                    for job in receiver.iter() {
+                        // a panicking proof must not take the thread down with it,
+                        // the dropped reply channel already reports the failure to the caller
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    }
+                })
+                .expect("fail to spawn prover thread");
+        }
+        Self { sender, size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> Result<(), ZkpError> {
+        self.sender.send(Box::new(job)).map_err(|e| {
+            ZkpError::WorkerUnavailable(e.to_string())
+        })
+    }
+}
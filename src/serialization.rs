@@ -0,0 +1,109 @@
+use plonkit::bellman_ce::PrimeField;
+use plonkit::bellman_ce::bn256::{Bn256, Fq, Fr, G1Affine};
+use plonkit::bellman_ce::pairing::CurveAffine;
+use plonkit::bellman_ce::pairing::ff::PrimeFieldRepr;
+use plonkit::bellman_ce::plonk::better_cs::cs::PlonkCsWidth4WithNextStepParams;
+use plonkit::bellman_ce::plonk::{Proof, VerificationKey};
+use primitive_types::U256;
+use crate::errors::ZkpError;
+
+const STATE_WIDTH: usize = 4;
+// 4 wire + 1 grand product + 4 quotient + 2 opening commitments, as (x, y) pairs,
+// 4 wire values at z, 1 at z*omega, 3 single evaluations and 3 permutation evaluations
+const SERIALIZED_PROOF_LEN: usize = (STATE_WIDTH + 1 + STATE_WIDTH + 2) * 2 + STATE_WIDTH + 1 + 3 + (STATE_WIDTH - 1);
+
+/// Inverse of `bellman_vk_codegen::serialize_proof`: rebuilds a proof from the
+/// `(public_inputs, serialized_proof)` uint256 arrays that the solidity verifier takes.
+/// `n` and `num_inputs` aren't part of that layout and are taken from the verification key.
+pub fn deserialize_proof(
+    vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>,
+    inputs: &[U256],
+    serialized_proof: &[U256],
+) -> Result<Proof<Bn256, PlonkCsWidth4WithNextStepParams>, ZkpError> {
+    if inputs.len() != vk.num_inputs {
+        return Err(ZkpError::InvalidInputs(format!("expected {} public inputs, got {}", vk.num_inputs, inputs.len())));
+    }
+    if serialized_proof.len() != SERIALIZED_PROOF_LEN {
+        return Err(ZkpError::InvalidProof(format!("expected {} words, got {}", SERIALIZED_PROOF_LEN, serialized_proof.len())));
+    }
+    let mut proof = Proof::<Bn256, PlonkCsWidth4WithNextStepParams>::empty();
+    proof.n = vk.n;
+    proof.num_inputs = vk.num_inputs;
+    proof.input_values = inputs.iter().map(to_fr).collect::<Result<_, _>>()?;
+
+    let mut words = serialized_proof.iter();
+    proof.wire_commitments = (0..STATE_WIDTH).map(|_| read_point(&mut words)).collect::<Result<_, _>>()?;
+    proof.grand_product_commitment = read_point(&mut words)?;
+    proof.quotient_poly_commitments = (0..STATE_WIDTH).map(|_| read_point(&mut words)).collect::<Result<_, _>>()?;
+    proof.wire_values_at_z = (0..STATE_WIDTH).map(|_| read_fr(&mut words)).collect::<Result<_, _>>()?;
+    proof.wire_values_at_z_omega = vec![read_fr(&mut words)?];
+    proof.grand_product_at_z_omega = read_fr(&mut words)?;
+    proof.quotient_polynomial_at_z = read_fr(&mut words)?;
+    proof.linearization_polynomial_at_z = read_fr(&mut words)?;
+    proof.permutation_polynomials_at_z = (0..STATE_WIDTH - 1).map(|_| read_fr(&mut words)).collect::<Result<_, _>>()?;
+    proof.opening_at_z_proof = read_point(&mut words)?;
+    proof.opening_at_z_omega_proof = read_point(&mut words)?;
+    Ok(proof)
+}
+
+fn read_fr<'a>(words: &mut impl Iterator<Item=&'a U256>) -> Result<Fr, ZkpError> {
+    to_fr(words.next().expect("length checked"))
+}
+
+fn read_point<'a>(words: &mut impl Iterator<Item=&'a U256>) -> Result<G1Affine, ZkpError> {
+    let (x, y) = (words.next().expect("length checked"), words.next().expect("length checked"));
+    // the codegen serializes the point at infinity as (0, 0), which isn't on the curve
+    if x.is_zero() && y.is_zero() {
+        return Ok(G1Affine::zero());
+    }
+    G1Affine::from_xy_checked(to_fq(x)?, to_fq(y)?).map_err(|e| {
+        ZkpError::InvalidProof(e.to_string())
+    })
+}
+
+fn to_fr(value: &U256) -> Result<Fr, ZkpError> {
+    let mut repr = <Fr as PrimeField>::Repr::default();
+    repr.read_be(&be_bytes(value)[..]).map_err(|e| ZkpError::InvalidProof(e.to_string()))?;
+    Fr::from_repr(repr).map_err(|e| ZkpError::InvalidProof(e.to_string()))
+}
+
+fn to_fq(value: &U256) -> Result<Fq, ZkpError> {
+    let mut repr = <Fq as PrimeField>::Repr::default();
+    repr.read_be(&be_bytes(value)[..]).map_err(|e| ZkpError::InvalidProof(e.to_string()))?;
+    Fq::from_repr(repr).map_err(|e| ZkpError::InvalidProof(e.to_string()))
+}
+
+fn be_bytes(value: &U256) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    value.to_big_endian(&mut buf);
+    buf
+}
+
+#[test]
+pub fn test_serialized_proof_roundtrip() {
+    use std::fs;
+    use crate::instance::ZKPFactory;
+
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let instance = ZKPFactory::default().build(String::from("demo"), r1cs).expect("fail to build");
+    let proof = instance.create_proof(wtns).expect("fail to prove");
+
+    let (inputs, serialized) = bellman_vk_codegen::serialize_proof(&proof);
+    let decoded = deserialize_proof(&instance.vk, &inputs, &serialized).expect("fail to deserialize");
+    let mut expected = Vec::new();
+    let mut actual = Vec::new();
+    proof.write(&mut expected).unwrap();
+    decoded.write(&mut actual).unwrap();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+pub fn test_read_point_at_infinity() {
+    let words = vec![U256::zero(), U256::zero(), U256::from(1), U256::from(2), U256::zero(), U256::from(1)];
+    let mut words = words.iter();
+    assert!(read_point(&mut words).unwrap().is_zero());
+    // (1, 2) is the generator
+    assert_eq!(read_point(&mut words).unwrap(), G1Affine::one());
+    assert!(read_point(&mut words).is_err());
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use plonkit::bellman_ce::{Circuit, plonk};
+use plonkit::bellman_ce::bn256::Bn256;
+use plonkit::bellman_ce::kate_commitment::{Crs, CrsForLagrangeForm, CrsForMonomialForm, CrsType};
+use plonkit::bellman_ce::worker::Worker;
+use plonkit::reader;
+use crate::errors::ZkpError;
+
+/// Loads every monomial setup key once and caches its lagrange form per domain size,
+/// so registering many circuits of the same size only pays the conversion once.
+/// When `cache_dir` is set the lagrange forms are also written to disk and reused across restarts.
+#[derive(Default)]
+pub struct SrsManager {
+    monomials: RwLock<HashMap<String, Arc<Crs<Bn256, CrsForMonomialForm>>>>,
+    lagranges: RwLock<HashMap<(String, usize), Arc<Crs<Bn256, CrsForLagrangeForm>>>>,
+    /// one lock per lagrange form, concurrent registrations of the same size wait for a single conversion
+    converting: Mutex<HashMap<(String, usize), Arc<Mutex<()>>>>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl SrsManager {
+    pub fn new(cache_dir: Option<PathBuf>) -> Result<Self, Error> {
+        if let Some(dir) = &cache_dir {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self { cache_dir, ..Default::default() })
+    }
+
+    pub fn monomial_form(&self, path: &str) -> Crs<Bn256, CrsForMonomialForm> {
+        if let Some(crs) = self.monomials.read().unwrap().get(path) {
+            return share(crs);
+        }
+        let crs = Arc::new(reader::load_key_monomial_form(path));
+        self.monomials.write().unwrap().insert(path.to_string(), crs.clone());
+        share(&crs)
+    }
+
+    pub fn lagrange_form(&self, path: &str, domain_size: usize) -> Result<Crs<Bn256, CrsForLagrangeForm>, ZkpError> {
+        let cache_key = (path.to_string(), domain_size);
+        if let Some(crs) = self.lagranges.read().unwrap().get(&cache_key) {
+            return Ok(share(crs));
+        }
+        let converting = self.converting.lock().unwrap().entry(cache_key.clone()).or_default().clone();
+        let _converting = converting.lock().unwrap();
+        if let Some(crs) = self.lagranges.read().unwrap().get(&cache_key) {
+            return Ok(share(crs));
+        }
+        let crs = match self.load_cached(path, domain_size) {
+            Some(crs) => crs,
+            None => {
+                let monomial = self.monomial_form(path);
+                if monomial.g1_bases.len() < domain_size {
+                    let largest_power = Some(monomial.g1_bases.len().trailing_zeros());
+                    return Err(ZkpError::SetupTooSmall { required_power: required_power(domain_size), largest_power });
+                }
+                let crs = Crs::<Bn256, CrsForLagrangeForm>::from_powers(&monomial, domain_size, &Worker::new());
+                // the form is still good for this process, it only has to be converted again after a restart
+                if let Err(e) = self.store_cached(path, domain_size, &crs) {
+                    println!("fail to write lagrange cache of {} for domain size {}, err:{}", path, domain_size, e);
+                }
+                crs
+            }
+        };
+        let crs = Arc::new(crs);
+        self.lagranges.write().unwrap().insert(cache_key, crs.clone());
+        Ok(share(&crs))
+    }
+
+    /// Named after the size and mtime of the monomial key too, so replacing the key doesn't reuse a stale form.
+    fn cache_file(&self, path: &str, domain_size: usize) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        let stem = Path::new(path).file_stem()?.to_string_lossy().to_string();
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(dir.join(format!("{}-{}-{}.lagrange_{}.key", stem, metadata.len(), modified, domain_size)))
+    }
+
+    /// An unreadable or truncated cache file is a miss, the form is converted again and the file rewritten.
+    fn load_cached(&self, path: &str, domain_size: usize) -> Option<Crs<Bn256, CrsForLagrangeForm>> {
+        let file = self.cache_file(path, domain_size).filter(|file| file.exists())?;
+        let crs = File::open(&file).and_then(|f| Crs::<Bn256, CrsForLagrangeForm>::read(BufReader::new(f)));
+        match crs {
+            Ok(crs) => Some(crs),
+            Err(e) => {
+                println!("ignore lagrange cache {}, err:{}", file.display(), e);
+                None
+            }
+        }
+    }
+
+    fn store_cached(&self, path: &str, domain_size: usize, crs: &Crs<Bn256, CrsForLagrangeForm>) -> Result<(), Error> {
+        if let Some(file) = self.cache_file(path, domain_size) {
+            // written aside and renamed, so a crash never leaves a half written cache file under the real name
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+            let tmp = file.with_extension(format!("key.tmp-{}-{}", std::process::id(), nanos));
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            crs.write(&mut writer)?;
+            writer.flush()?;
+            fs::rename(tmp, file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Setup keys found in a directory, indexed by their power (`setup_2^N.key` holds 2^N bases).
+#[derive(Default, Debug)]
+pub struct SetupKeyCatalog {
+    keys: BTreeMap<u32, PathBuf>,
+}
+
+impl SetupKeyCatalog {
+    pub fn scan(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut keys = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let power = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("setup_2^"))
+                .and_then(|name| name.strip_suffix(".key"))
+                .and_then(|power| power.parse::<u32>().ok());
+            if let Some(power) = power {
+                keys.insert(power, path);
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// The smallest key whose size covers `domain_size`, with its power.
+    pub fn pick(&self, domain_size: usize) -> Result<(u32, &Path), ZkpError> {
+        let required = required_power(domain_size);
+        self.keys.range(required..).next()
+            .map(|(power, path)| (*power, path.as_path()))
+            .ok_or_else(|| {
+                ZkpError::SetupTooSmall { required_power: required, largest_power: self.keys.keys().last().cloned() }
+            })
+    }
+}
+
+pub fn required_power(domain_size: usize) -> u32 {
+    domain_size.next_power_of_two().trailing_zeros()
+}
+
+/// The PLONK domain size of `circuit` after transpilation, which is the size the lagrange form has to match.
+pub fn domain_size<C: Circuit<Bn256> + Clone>(circuit: C) -> Result<usize, ZkpError> {
+    let (gates_count, _) = plonk::transpile_with_gates_count(circuit).map_err(|e| {
+        ZkpError::InvalidR1cs(e.to_string())
+    })?;
+    Ok((gates_count + 1).next_power_of_two())
+}
+
+// crs bases are behind Arcs, so handing out a copy doesn't duplicate the points
+fn share<T: CrsType>(crs: &Crs<Bn256, T>) -> Crs<Bn256, T> {
+    Crs::new_in_arc(crs.g1_bases.clone(), crs.g2_monomial_bases.clone())
+}
+
+#[test]
+pub fn test_catalog_pick() {
+    let catalog = SetupKeyCatalog::scan(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup")).unwrap();
+    let (power, _) = catalog.pick(512).unwrap();
+    assert_eq!(power, 10);
+    assert!(catalog.pick(1 << 30).is_err());
+}
+
+#[test]
+pub fn test_lagrange_form_cached() {
+    let srs = SrsManager::default();
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup/setup_2^10.key");
+    let first = srs.lagrange_form(path, 512).unwrap();
+    let second = srs.lagrange_form(path, 512).unwrap();
+    assert!(Arc::ptr_eq(&first.g1_bases, &second.g1_bases));
+}
+
+#[test]
+pub fn test_lagrange_form_converted_once() {
+    let srs = Arc::new(SrsManager::default());
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup/setup_2^10.key");
+    let handles: Vec<_> = (0..4).map(|_| {
+        let srs = srs.clone();
+        std::thread::spawn(move || srs.lagrange_form(path, 512).unwrap())
+    }).collect();
+    let forms: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    assert!(forms.iter().all(|form| Arc::ptr_eq(&form.g1_bases, &forms[0].g1_bases)));
+}
+
+#[test]
+pub fn test_lagrange_form_disk_cache() {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let dir = std::env::temp_dir().join(format!("zkp-srs-{}", nanos));
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/plonk/setup/setup_2^10.key");
+    let srs = SrsManager::new(Some(dir.clone())).unwrap();
+    let file = srs.cache_file(path, 512).unwrap();
+    // a truncated file left by an interrupted write is converted again
+    fs::write(&file, [1u8, 2, 3]).unwrap();
+    let converted = srs.lagrange_form(path, 512).unwrap();
+
+    let restarted = SrsManager::new(Some(dir.clone())).unwrap();
+    let cached = restarted.load_cached(path, 512).expect("cache file not rewritten");
+    assert_eq!(cached.g1_bases.as_slice(), converted.g1_bases.as_slice());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
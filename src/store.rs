@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use ethers::utils::{hex, keccak256};
+use serde::{Serialize, Deserialize};
+use crate::contract_adapter::Deployment;
+
+const R1CS_FILE: &'static str = "circuit.r1cs";
+const VK_FILE: &'static str = "vk.bin";
+const META_FILE: &'static str = "meta.json";
+const WASM_FILE: &'static str = "circuit.wasm";
+/// Longest key in bytes, `DirCircuitStore` names directories after the hex encoded key and most filesystems stop at 255.
+pub const MAX_KEY_LEN: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CircuitMeta {
+    pub key: String,
+    pub registered_at: u64,
+    pub r1cs_size: usize,
+    /// bumped every time the key is registered again in `version` mode
+    pub version: u32,
+    /// hex keccak256 of the r1cs bytes, tells an identical re-registration from a replacement
+    pub r1cs_hash: String,
+    /// verifier contract deployed by `POST /circuits/<key>/deploy`
+    #[serde(default)]
+    pub deployment: Option<Deployment>,
+}
+
+impl CircuitMeta {
+    pub fn new(key: String, r1cs: &[u8], version: u32) -> Self {
+        let registered_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        Self { key, registered_at, r1cs_size: r1cs.len(), version, r1cs_hash: r1cs_hash(r1cs), deployment: None }
+    }
+}
+
+pub fn r1cs_hash(r1cs: &[u8]) -> String {
+    hex::encode(keccak256(r1cs))
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitRecord {
+    pub meta: CircuitMeta,
+    pub r1cs: Vec<u8>,
+    pub vk: Vec<u8>,
+    /// circom witness generator, when the circuit was registered with one
+    pub wasm: Option<Vec<u8>>,
+}
+
+/// Persists registered circuits so the container can be rebuilt after a restart.
+pub trait CircuitStore: Send + Sync {
+    fn save(&self, record: &CircuitRecord) -> Result<(), Error>;
+    fn load_all(&self) -> Result<Vec<CircuitRecord>, Error>;
+    /// Rewrites the meta of an already saved record, the circuit files stay as they are.
+    fn save_meta(&self, meta: &CircuitMeta) -> Result<(), Error>;
+    fn remove(&self, key: &str, version: u32) -> Result<(), Error>;
+}
+
+/// Keeps records for the lifetime of the process only; used when no data dir is configured.
+#[derive(Default)]
+pub struct MemoryCircuitStore {
+    records: RwLock<HashMap<(String, u32), CircuitRecord>>,
+}
+
+impl CircuitStore for MemoryCircuitStore {
+    fn save(&self, record: &CircuitRecord) -> Result<(), Error> {
+        self.records.write().unwrap().insert((record.meta.key.clone(), record.meta.version), record.clone());
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<Vec<CircuitRecord>, Error> {
+        Ok(self.records.read().unwrap().values().cloned().collect())
+    }
+
+    fn save_meta(&self, meta: &CircuitMeta) -> Result<(), Error> {
+        let mut records = self.records.write().unwrap();
+        let record = records.get_mut(&(meta.key.clone(), meta.version)).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("circuit {} version {} is not saved", meta.key, meta.version))
+        })?;
+        record.meta = meta.clone();
+        Ok(())
+    }
+
+    fn remove(&self, key: &str, version: u32) -> Result<(), Error> {
+        self.records.write().unwrap().remove(&(key.to_string(), version));
+        Ok(())
+    }
+}
+
+/// Stores every circuit version in its own sub directory of `root`:
+/// `<root>/<hex(key)>/<version>/{circuit.r1cs, vk.bin, meta.json}` plus an optional `circuit.wasm`.
+/// The key is hex encoded so client chosen keys can't escape the data dir.
+pub struct DirCircuitStore {
+    root: PathBuf,
+}
+
+impl DirCircuitStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    fn key_dir(&self, key: &str) -> PathBuf {
+        self.root.join(hex::encode(key.as_bytes()))
+    }
+
+    fn circuit_dir(&self, key: &str, version: u32) -> PathBuf {
+        self.key_dir(key).join(version.to_string())
+    }
+
+    fn load_one(&self, dir: PathBuf) -> Result<CircuitRecord, Error> {
+        let meta_bytes = fs::read(dir.join(META_FILE))?;
+        let meta: CircuitMeta = serde_json::from_slice(meta_bytes.as_slice()).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, e)
+        })?;
+        let r1cs = fs::read(dir.join(R1CS_FILE))?;
+        let vk = fs::read(dir.join(VK_FILE))?;
+        let wasm_path = dir.join(WASM_FILE);
+        let wasm = if wasm_path.exists() { Some(fs::read(wasm_path)?) } else { None };
+        Ok(CircuitRecord { meta, r1cs, vk, wasm })
+    }
+}
+
+impl CircuitStore for DirCircuitStore {
+    fn save(&self, record: &CircuitRecord) -> Result<(), Error> {
+        if record.meta.key.len() > MAX_KEY_LEN {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("circuit key is longer than {} bytes", MAX_KEY_LEN)));
+        }
+        let dir = self.circuit_dir(record.meta.key.as_str(), record.meta.version);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(R1CS_FILE), record.r1cs.as_slice())?;
+        fs::write(dir.join(VK_FILE), record.vk.as_slice())?;
+        match &record.wasm {
+            Some(wasm) => fs::write(dir.join(WASM_FILE), wasm.as_slice())?,
+            // left over from an overwritten registration
+            None if dir.join(WASM_FILE).exists() => fs::remove_file(dir.join(WASM_FILE))?,
+            None => {}
+        }
+        // meta is written last, a directory without it is an interrupted save and gets skipped on load
+        self.save_meta(&record.meta)
+    }
+
+    fn load_all(&self) -> Result<Vec<CircuitRecord>, Error> {
+        let mut records = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let key_dir = entry?.path();
+            if !key_dir.is_dir() {
+                continue;
+            }
+            let mut dirs = Vec::new();
+            for entry in fs::read_dir(&key_dir)? {
+                let dir = entry?.path();
+                if dir.is_dir() {
+                    dirs.push(dir);
+                }
+            }
+            for dir in dirs.into_iter().filter(|dir| dir.join(META_FILE).exists()) {
+                // one corrupt record must not keep the others from loading
+                match self.load_one(dir.clone()) {
+                    Ok(record) => records.push(record),
+                    Err(e) => println!("skip stored circuit {}, err:{}", dir.display(), e),
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn save_meta(&self, meta: &CircuitMeta) -> Result<(), Error> {
+        let dir = self.circuit_dir(meta.key.as_str(), meta.version);
+        let bytes = serde_json::to_vec_pretty(meta).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, e)
+        })?;
+        fs::write(dir.join(META_FILE), bytes)
+    }
+
+    fn remove(&self, key: &str, version: u32) -> Result<(), Error> {
+        let key_dir = self.key_dir(key);
+        let dir = self.circuit_dir(key, version);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        if key_dir.exists() && fs::read_dir(&key_dir)?.next().is_none() {
+            fs::remove_dir(key_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+pub fn test_dir_store_restore() {
+    use std::sync::Arc;
+    use crate::instance::{ProveRequest, RegisterRequest, ZKPFactory, ZKPProverContainer};
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let root = std::env::temp_dir().join(format!("zkp-store-{}", nanos));
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+
+    let mut container = ZKPProverContainer::with_store(Arc::new(DirCircuitStore::new(root.clone()).unwrap()), ZKPFactory::default());
+    let registered = container.register(RegisterRequest::new(String::from("demo"), r1cs)).expect("fail to register");
+    drop(container);
+    // a corrupt record is skipped
+    let broken = DirCircuitStore::new(root.clone()).unwrap().circuit_dir("broken", 1);
+    fs::create_dir_all(&broken).unwrap();
+    fs::write(broken.join(META_FILE), "not json").unwrap();
+
+    let container = ZKPProverContainer::with_store(Arc::new(DirCircuitStore::new(root.clone()).unwrap()), ZKPFactory::default());
+    let restored = container.get_vk("demo").expect("circuit not restored");
+    assert_eq!(registered.vk, restored);
+    assert_eq!(container.circuit_info("demo").unwrap().hash, registered.hash);
+    assert_eq!(container.list_circuits().len(), 1);
+    container.prove(ProveRequest { key: String::from("demo"), wtns }).expect("fail to prove");
+    fs::remove_dir_all(root).unwrap();
+}
@@ -1,5 +1,120 @@
-use ethers::contract::abigen;
+use std::sync::Arc;
+use ethers::contract::{abigen, ContractError};
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Http, HttpClientError, Middleware, Provider, ProviderError};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, H256};
+use primitive_types::U256;
+use serde::{Serialize, Deserialize};
+use crate::errors::ZkpError;
+
 abigen!(
     KeyedVerifier,
     "config/contracts/KeyedVerifier.abi"
-);
\ No newline at end of file
+);
+
+/// Result of asking a deployed verifier about a proof.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OnChainVerifyResponse {
+    pub verify: bool,
+    pub address: Address,
+    /// set when the proof was also sent in a transaction
+    pub tx_hash: Option<H256>,
+    pub gas_used: Option<u64>,
+}
+
+/// Calls `verify_serialized_proof` of deployed `KeyedVerifier` contracts.
+pub struct OnChainVerifier {
+    provider: Provider<Http>,
+    /// needed to send transactions, calls work without it
+    wallet: Option<LocalWallet>,
+    chain_id: Option<u64>,
+}
+
+impl OnChainVerifier {
+    pub fn new(rpc_url: &str) -> Result<Self, ZkpError> {
+        let provider = Provider::<Http>::try_from(rpc_url).map_err(|e| {
+            ZkpError::InvalidDeployerConfig(format!("rpc url {}: {}", rpc_url, e))
+        })?;
+        Ok(Self { provider, wallet: None, chain_id: None })
+    }
+
+    pub fn with_signer(provider: Provider<Http>, wallet: LocalWallet, chain_id: Option<u64>) -> Self {
+        Self { provider, wallet: Some(wallet), chain_id }
+    }
+
+    /// `eth_call`s the verifier at `address`, and when `transact` is set and the call accepted the proof,
+    /// also sends it in a transaction to see it accepted in a block.
+    pub async fn verify(&self, address: Address, inputs: &[U256], serialized_proof: &[U256], transact: bool) -> Result<OnChainVerifyResponse, ZkpError> {
+        let inputs: Vec<ethers::types::U256> = inputs.iter().map(to_ethers).collect();
+        let serialized_proof: Vec<ethers::types::U256> = serialized_proof.iter().map(to_ethers).collect();
+        let contract = KeyedVerifier::new(address, Arc::new(self.provider.clone()));
+        let verify = match contract.verify_serialized_proof(inputs.clone(), serialized_proof.clone()).call().await {
+            Ok(verify) => verify,
+            // the verifier reverts instead of returning false on malformed points and out of range values
+            Err(e) if is_revert(&e) => false,
+            Err(e) => return Err(ZkpError::ChainRequestFailed(e.to_string())),
+        };
+        let mut resp = OnChainVerifyResponse { verify, address, tx_hash: None, gas_used: None };
+        if !transact || !verify {
+            return Ok(resp);
+        }
+
+        let wallet = self.wallet.clone().ok_or(ZkpError::DeployerNotConfigured)?;
+        let chain_id = self.chain_id().await?;
+        let client = Arc::new(SignerMiddleware::new(self.provider.clone(), wallet.with_chain_id(chain_id)));
+        let contract = KeyedVerifier::new(address, client);
+        let call = contract.verify_serialized_proof(inputs, serialized_proof);
+        let pending_tx = call.send().await.map_err(|e| {
+            ZkpError::ChainRequestFailed(e.to_string())
+        })?;
+        let receipt = pending_tx.await.map_err(|e| {
+            ZkpError::ChainRequestFailed(e.to_string())
+        })?.ok_or_else(|| ZkpError::ChainRequestFailed(String::from("verify transaction was dropped")))?;
+        resp.verify = receipt.status.map(|s| s.as_u64() == 1).unwrap_or(false);
+        resp.tx_hash = Some(receipt.transaction_hash);
+        resp.gas_used = receipt.gas_used.map(|g| g.as_u64());
+        Ok(resp)
+    }
+
+    /// The configured chain id, or the one the node reports.
+    pub async fn chain_id(&self) -> Result<u64, ZkpError> {
+        match self.chain_id {
+            Some(chain_id) => Ok(chain_id),
+            None => Ok(self.provider.get_chainid().await.map_err(|e| {
+                ZkpError::ChainRequestFailed(e.to_string())
+            })?.as_u64()),
+        }
+    }
+}
+
+/// Whether the node answered that the call reverted, as opposed to not answering at all.
+/// Nodes report a revert as a json-rpc error, code 3 when it carries revert data.
+fn is_revert(e: &ContractError<Provider<Http>>) -> bool {
+    let e = match e {
+        ContractError::Revert(_) => return true,
+        ContractError::MiddlewareError(e) | ContractError::ProviderError(e) => e,
+        _ => return false,
+    };
+    match e {
+        ProviderError::JsonRpcClientError(e) => match e.downcast_ref::<HttpClientError>() {
+            Some(HttpClientError::JsonRpcError(e)) => e.code == 3 || e.message.starts_with("execution reverted"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The prover's `primitive_types` is older than the one ethers uses.
+fn to_ethers(v: &U256) -> ethers::types::U256 {
+    let mut buf = [0u8; 32];
+    v.to_big_endian(&mut buf);
+    ethers::types::U256::from_big_endian(&buf)
+}
+
+#[test]
+pub fn test_to_ethers() {
+    let v = U256::from_dec_str("21888242871839275222246405745257275088548364400416034343698204186575808495616").unwrap();
+    assert_eq!(to_ethers(&v).to_string(), v.to_string());
+    assert!(OnChainVerifier::new("not a url").is_err());
+}
@@ -0,0 +1,150 @@
+use std::str::FromStr;
+use plonkit::bellman_ce::{CurveAffine, PrimeField};
+use plonkit::bellman_ce::bn256::{Bn256, Fq, Fq2, Fr, G1Affine, G2Affine};
+use plonkit::bellman_ce::pairing::ff::PrimeFieldRepr;
+use plonkit::bellman_ce::plonk::better_cs::cs::PlonkCsWidth4WithNextStepParams;
+use plonkit::bellman_ce::plonk::domains::Domain;
+use plonkit::bellman_ce::plonk::VerificationKey;
+use primitive_types::U256;
+use serde_json::{json, Value};
+use crate::errors::ZkpError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VkFormat {
+    /// `VerificationKey::write`, what `/register` returns
+    Bin,
+    Json,
+    Snarkjs,
+}
+
+impl FromStr for VkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(VkFormat::Bin),
+            "json" => Ok(VkFormat::Json),
+            "snarkjs" => Ok(VkFormat::Snarkjs),
+            _ => Err(format!("unknown vk format {}, expected bin, json or snarkjs", s)),
+        }
+    }
+}
+
+pub fn to_bytes(vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>) -> Result<Vec<u8>, ZkpError> {
+    let mut bytes = Vec::new();
+    vk.write(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>, ZkpError> {
+    VerificationKey::read(bytes).map_err(|e| {
+        ZkpError::InvalidVerificationKey(e.to_string())
+    })
+}
+
+/// Every field of the vk, with big-endian hex coordinates.
+pub fn to_json(vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>) -> Value {
+    json!({
+        "n": vk.n,
+        "domain_size": (vk.n + 1).next_power_of_two(),
+        "num_inputs": vk.num_inputs,
+        "selector_commitments": vk.selector_commitments.iter().map(|p| g1(p, hex)).collect::<Vec<_>>(),
+        "next_step_selector_commitments": vk.next_step_selector_commitments.iter().map(|p| g1(p, hex)).collect::<Vec<_>>(),
+        "permutation_commitments": vk.permutation_commitments.iter().map(|p| g1(p, hex)).collect::<Vec<_>>(),
+        "non_residues": vk.non_residues.iter().map(hex).collect::<Vec<_>>(),
+        "g2_elements": vk.g2_elements.iter().map(|p| g2(p, hex)).collect::<Vec<_>>(),
+    })
+}
+
+/// The layout of snarkjs' PLONK `verification_key.json`, decimal strings and projective `[x, y, 1]` points.
+/// This prover uses 4 wires plus a next step selector where snarkjs has 3 wires, so the extra
+/// `Q4`, `Qd_next`, `S4` and `k3` entries are added and snarkjs itself can't verify against it.
+pub fn to_snarkjs(vk: &VerificationKey<Bn256, PlonkCsWidth4WithNextStepParams>) -> Result<Value, ZkpError> {
+    let domain = Domain::<Fr>::new_for_size((vk.n + 1) as u64).map_err(|e| {
+        ZkpError::InvalidVerificationKey(e.to_string())
+    })?;
+    let selector = |i: usize| g1_projective(&vk.selector_commitments[i]);
+    Ok(json!({
+        "protocol": "plonk",
+        "curve": "bn128",
+        "nPublic": vk.num_inputs,
+        "power": domain.power_of_two,
+        "k1": decimal(&vk.non_residues[0]),
+        "k2": decimal(&vk.non_residues[1]),
+        "k3": decimal(&vk.non_residues[2]),
+        "Ql": selector(0),
+        "Qr": selector(1),
+        "Qo": selector(2),
+        "Q4": selector(3),
+        "Qm": selector(4),
+        "Qc": selector(5),
+        "Qd_next": g1_projective(&vk.next_step_selector_commitments[0]),
+        "S1": g1_projective(&vk.permutation_commitments[0]),
+        "S2": g1_projective(&vk.permutation_commitments[1]),
+        "S3": g1_projective(&vk.permutation_commitments[2]),
+        "S4": g1_projective(&vk.permutation_commitments[3]),
+        "X_2": g2_projective(&vk.g2_elements[1]),
+        "w": decimal(&domain.generator),
+    }))
+}
+
+fn g1(p: &G1Affine, f: fn(&Fq) -> String) -> Value {
+    let (x, y) = p.into_xy_unchecked();
+    json!({ "x": f(&x), "y": f(&y) })
+}
+
+fn g2(p: &G2Affine, f: fn(&Fq) -> String) -> Value {
+    let (x, y) = p.into_xy_unchecked();
+    json!({ "x": fq2(&x, f), "y": fq2(&y, f) })
+}
+
+fn g1_projective(p: &G1Affine) -> Value {
+    let (x, y) = p.into_xy_unchecked();
+    json!([decimal(&x), decimal(&y), "1"])
+}
+
+fn g2_projective(p: &G2Affine) -> Value {
+    let (x, y) = p.into_xy_unchecked();
+    json!([fq2(&x, decimal), fq2(&y, decimal), ["1", "0"]])
+}
+
+fn fq2(v: &Fq2, f: fn(&Fq) -> String) -> Value {
+    json!([f(&v.c0), f(&v.c1)])
+}
+
+fn be_bytes<F: PrimeField>(v: &F) -> Vec<u8> {
+    let mut buf = Vec::new();
+    v.into_repr().write_be(&mut buf).expect("writing to a vec can't fail");
+    buf
+}
+
+fn hex<F: PrimeField>(v: &F) -> String {
+    format!("0x{}", ethers::utils::hex::encode(be_bytes(v)))
+}
+
+fn decimal<F: PrimeField>(v: &F) -> String {
+    U256::from_big_endian(be_bytes(v).as_slice()).to_string()
+}
+
+#[test]
+pub fn test_vk_formats() {
+    use std::fs;
+    use crate::instance::ZKPFactory;
+
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let instance = ZKPFactory::default().build(String::from("demo"), r1cs).expect("fail to build");
+
+    let bytes = to_bytes(&instance.vk).expect("fail to write");
+    let read = from_bytes(bytes.as_slice()).expect("fail to read");
+    assert_eq!(to_json(&read), to_json(&instance.vk));
+
+    let v = to_json(&instance.vk);
+    assert_eq!(v["num_inputs"], json!(instance.vk.num_inputs));
+    assert_eq!(v["selector_commitments"].as_array().unwrap().len(), 6);
+    assert_eq!(v["permutation_commitments"].as_array().unwrap().len(), 4);
+
+    let v = to_snarkjs(&instance.vk).expect("fail to export");
+    assert_eq!(v["nPublic"], json!(instance.vk.num_inputs));
+    assert_eq!(1usize << v["power"].as_u64().unwrap(), (instance.vk.n + 1).next_power_of_two());
+    assert_eq!(v["Qm"][2], json!("1"));
+}
@@ -0,0 +1,313 @@
+use plonkit::bellman_ce::Field;
+use plonkit::bellman_ce::bn256::{Bn256, Fr};
+use plonkit::circom_circuit::R1CS;
+use primitive_types::U256;
+use serde_json::Value;
+use wasmer::{imports, Function, Instance, Module, NativeFunc, RuntimeError, Store};
+use crate::errors::ZkpError;
+
+const WTNS_VERSION: u32 = 2;
+
+/// Runs the witness generator that `circom --wasm` emits for a circuit,
+/// following the calling convention of circom 2's `witness_calculator.js`.
+/// The module is compiled once, every calculation gets a fresh instance.
+pub struct WitnessCalculator {
+    store: Store,
+    module: Module,
+    input_size: Option<usize>,
+}
+
+impl WitnessCalculator {
+    pub fn new(wasm: &[u8]) -> Result<Self, ZkpError> {
+        let store = Store::default();
+        let module = Module::new(&store, wasm).map_err(|e| {
+            ZkpError::InvalidWasm(e.to_string())
+        })?;
+        let calculator = Self { store, module, input_size: None };
+        // fail at registration rather than on the first proof
+        calculator.instantiate()?;
+        Ok(calculator)
+    }
+
+    /// Number of input values the circuit takes, for runtimes that don't export `getInputSize`.
+    /// Early circom 2 releases run the circuit with whatever is set, so missing inputs can't be told otherwise.
+    pub fn with_input_size(mut self, input_size: usize) -> Self {
+        self.input_size = Some(input_size);
+        self
+    }
+
+    /// Computes the full witness for a json object of named input signals,
+    /// values are decimal strings or numbers, array signals are given as (nested) arrays.
+    pub fn calculate(&self, inputs: &str) -> Result<Vec<U256>, ZkpError> {
+        self.run(inputs).map(|(_, witness)| witness)
+    }
+
+    /// Same as `calculate`, encoded as a `.wtns` file so it can go through the regular prove path.
+    pub fn calculate_wtns(&self, inputs: &str) -> Result<Vec<u8>, ZkpError> {
+        let (prime, witness) = self.run(inputs)?;
+        Ok(encode_wtns(&prime, witness.as_slice()))
+    }
+
+    fn run(&self, inputs: &str) -> Result<(U256, Vec<U256>), ZkpError> {
+        let inputs: serde_json::Map<String, Value> = serde_json::from_str(inputs).map_err(|e| {
+            ZkpError::InvalidInputs(e.to_string())
+        })?;
+        let runtime = Runtime::new(self.instantiate()?)?;
+        let prime = runtime.prime()?;
+        runtime.init()?;
+
+        let mut input_counter = 0;
+        for (name, value) in inputs.iter() {
+            let (msb, lsb) = fnv_hash(name.as_str());
+            let mut values = Vec::new();
+            flatten(name.as_str(), value, &prime, &mut values)?;
+            // without it an unknown signal is reported by the runtime's exception handler instead
+            if let Some(get_input_signal_size) = &runtime.get_input_signal_size {
+                let size = runtime.call(|_| get_input_signal_size.call(msb, lsb))?;
+                if size < 0 {
+                    return Err(ZkpError::InvalidInputs(format!("signal {} is not an input of the circuit", name)));
+                }
+                if values.len() != size as usize {
+                    return Err(ZkpError::InvalidInputs(format!("signal {} expects {} values, got {}", name, size, values.len())));
+                }
+            }
+            for (i, v) in values.iter().enumerate() {
+                runtime.write(v)?;
+                runtime.call(|f| f.set_input_signal.call(msb, lsb, i as i32))?;
+                input_counter += 1;
+            }
+        }
+        let input_size = match &runtime.get_input_size {
+            Some(get_input_size) => Some(runtime.call(|_| get_input_size.call())? as usize),
+            None => self.input_size,
+        };
+        if let Some(input_size) = input_size.filter(|size| input_counter < *size) {
+            return Err(ZkpError::InvalidInputs(format!("{} of {} input values are set", input_counter, input_size)));
+        }
+
+        let witness_size = runtime.call(|f| f.get_witness_size.call())?;
+        let witness = (0..witness_size).map(|i| {
+            runtime.call(|f| f.get_witness.call(i))?;
+            runtime.read()
+        }).collect::<Result<Vec<_>, _>>()?;
+        Ok((prime, witness))
+    }
+
+    fn instantiate(&self) -> Result<Instance, ZkpError> {
+        let import_object = imports! {
+            "runtime" => {
+                "exceptionHandler" => Function::new_native(&self.store, |code: i32| -> Result<(), RuntimeError> {
+                    Err(RuntimeError::new(format!("circuit assertion failed with code {}", code)))
+                }),
+                "printErrorMessage" => Function::new_native(&self.store, || {}),
+                "writeBufferMessage" => Function::new_native(&self.store, || {}),
+                "showSharedRWMemory" => Function::new_native(&self.store, || {}),
+            },
+        };
+        Instance::new(&self.module, &import_object).map_err(|e| {
+            ZkpError::InvalidWasm(e.to_string())
+        })
+    }
+}
+
+struct Runtime {
+    _instance: Instance,
+    n32: i32,
+    init: NativeFunc<i32, ()>,
+    get_raw_prime: NativeFunc<(), ()>,
+    read_shared_rw_memory: NativeFunc<i32, i32>,
+    write_shared_rw_memory: NativeFunc<(i32, i32), ()>,
+    /// only exported by newer circom 2 runtimes
+    get_input_signal_size: Option<NativeFunc<(i32, i32), i32>>,
+    set_input_signal: NativeFunc<(i32, i32, i32), ()>,
+    get_input_size: Option<NativeFunc<(), i32>>,
+    get_witness_size: NativeFunc<(), i32>,
+    get_witness: NativeFunc<i32, ()>,
+}
+
+impl Runtime {
+    fn new(instance: Instance) -> Result<Self, ZkpError> {
+        let exports = instance.exports.clone();
+        let missing = |e: wasmer::ExportError| ZkpError::InvalidWasm(e.to_string());
+        let n32 = exports.get_native_function::<(), i32>("getFieldNumLen32").map_err(missing)?
+            .call().map_err(|e| ZkpError::InvalidWasm(e.to_string()))?;
+        // field elements are handled as U256, which covers every curve circom supports
+        if n32 <= 0 || n32 > 8 {
+            return Err(ZkpError::InvalidWasm(format!("unsupported field size of {} words", n32)));
+        }
+        Ok(Self {
+            _instance: instance,
+            n32,
+            init: exports.get_native_function("init").map_err(missing)?,
+            get_raw_prime: exports.get_native_function("getRawPrime").map_err(missing)?,
+            read_shared_rw_memory: exports.get_native_function("readSharedRWMemory").map_err(missing)?,
+            write_shared_rw_memory: exports.get_native_function("writeSharedRWMemory").map_err(missing)?,
+            get_input_signal_size: exports.get_native_function("getInputSignalSize").ok(),
+            set_input_signal: exports.get_native_function("setInputSignal").map_err(missing)?,
+            get_input_size: exports.get_native_function("getInputSize").ok(),
+            get_witness_size: exports.get_native_function("getWitnessSize").map_err(missing)?,
+            get_witness: exports.get_native_function("getWitness").map_err(missing)?,
+        })
+    }
+
+    fn call<T>(&self, f: impl FnOnce(&Self) -> Result<T, RuntimeError>) -> Result<T, ZkpError> {
+        f(self).map_err(|e| ZkpError::WitnessCalculationFailed(e.message()))
+    }
+
+    fn init(&self) -> Result<(), ZkpError> {
+        // 1 enables the sanity checks of the generated code
+        self.call(|f| f.init.call(1))
+    }
+
+    fn prime(&self) -> Result<U256, ZkpError> {
+        self.call(|f| f.get_raw_prime.call())?;
+        self.read()
+    }
+
+    /// Reads the field element in the shared memory, stored as 32 bit words least significant first.
+    fn read(&self) -> Result<U256, ZkpError> {
+        let mut value = U256::zero();
+        for j in (0..self.n32).rev() {
+            let word = self.call(|f| f.read_shared_rw_memory.call(j))? as u32;
+            value = (value << 32) | U256::from(word);
+        }
+        Ok(value)
+    }
+
+    fn write(&self, value: &U256) -> Result<(), ZkpError> {
+        for j in 0..self.n32 {
+            let word = ((*value >> (32 * j as usize)) & U256::from(u32::MAX)).low_u32();
+            self.call(|f| f.write_shared_rw_memory.call(j, word as i32))?;
+        }
+        Ok(())
+    }
+}
+
+/// 64 bit FNV-1a of the signal name, split in the (msb, lsb) halves the wasm expects.
+fn fnv_hash(name: &str) -> (i32, i32) {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    ((hash >> 32) as u32 as i32, hash as u32 as i32)
+}
+
+fn flatten(name: &str, value: &Value, prime: &U256, out: &mut Vec<U256>) -> Result<(), ZkpError> {
+    match value {
+        Value::Array(values) => {
+            for v in values {
+                flatten(name, v, prime, out)?;
+            }
+        }
+        Value::String(s) => out.push(parse_field(name, s.as_str(), prime)?),
+        Value::Number(n) => out.push(parse_field(name, n.to_string().as_str(), prime)?),
+        Value::Bool(b) => out.push(U256::from(*b as u8)),
+        _ => return Err(ZkpError::InvalidInputs(format!("signal {} has a value that is not a number", name))),
+    }
+    Ok(())
+}
+
+/// Parses a decimal value and reduces it into the field, negative values wrap around the prime.
+fn parse_field(name: &str, s: &str, prime: &U256) -> Result<U256, ZkpError> {
+    let invalid = || ZkpError::InvalidInputs(format!("signal {} has an invalid value {}", name, s));
+    let (negative, digits) = match s.trim().strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.trim()),
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    let mut value = U256::zero();
+    for c in digits.chars() {
+        let digit = c.to_digit(10).ok_or_else(invalid)?;
+        value = value.checked_mul(U256::from(10)).and_then(|v| v.checked_add(U256::from(digit))).ok_or_else(invalid)?;
+    }
+    let value = value % *prime;
+    if negative && !value.is_zero() {
+        Ok(*prime - value)
+    } else {
+        Ok(value)
+    }
+}
+
+/// Index of the first constraint `a * b = c` that `witness` doesn't satisfy, the length must already be checked.
+pub fn first_unsatisfied(r1cs: &R1CS<Bn256>, witness: &[Fr]) -> Option<usize> {
+    let eval = |lc: &Vec<(usize, Fr)>| {
+        lc.iter().fold(Fr::zero(), |mut acc, (index, coeff)| {
+            let mut term = witness[*index];
+            term.mul_assign(coeff);
+            acc.add_assign(&term);
+            acc
+        })
+    };
+    r1cs.constraints.iter().position(|(a, b, c)| {
+        let mut ab = eval(a);
+        ab.mul_assign(&eval(b));
+        ab != eval(c)
+    })
+}
+
+/// Encodes a witness in the binary `.wtns` layout read by `load_witness_from_array`.
+pub fn encode_wtns(prime: &U256, witness: &[U256]) -> Vec<u8> {
+    let n8 = 32usize;
+    let mut out = Vec::with_capacity(12 + 12 + n8 + 8 + 12 + n8 * witness.len());
+    out.extend_from_slice(b"wtns");
+    out.extend_from_slice(&WTNS_VERSION.to_le_bytes());
+    out.extend_from_slice(&2u32.to_le_bytes());
+
+    // header: field size, prime, number of values
+    out.extend_from_slice(&1u32.to_le_bytes());
+    out.extend_from_slice(&((4 + n8 + 4) as u64).to_le_bytes());
+    out.extend_from_slice(&(n8 as u32).to_le_bytes());
+    out.extend_from_slice(&le_bytes(prime));
+    out.extend_from_slice(&(witness.len() as u32).to_le_bytes());
+
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.extend_from_slice(&((n8 * witness.len()) as u64).to_le_bytes());
+    for value in witness {
+        out.extend_from_slice(&le_bytes(value));
+    }
+    out
+}
+
+fn le_bytes(value: &U256) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    value.to_little_endian(&mut buf);
+    buf
+}
+
+#[test]
+pub fn test_calculate_witness() {
+    use std::fs;
+
+    // built by circom 2.0.0 from mycircuit.circom, the circuit behind witness.wtns (c <== a * b).
+    // That runtime doesn't export getInputSize, the two inputs come from the r1cs header
+    let wasm = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.wasm")).expect("fail");
+    let expected = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let calculator = WitnessCalculator::new(wasm.as_slice()).expect("fail to load wasm").with_input_size(2);
+
+    let witness = calculator.calculate(r#"{"a": "1", "b": 2}"#).expect("fail to calculate");
+    assert_eq!(witness, vec![U256::from(1), U256::from(2), U256::from(1), U256::from(2)]);
+    assert_eq!(calculator.calculate_wtns(r#"{"a": "1", "b": "2"}"#).expect("fail to calculate"), expected);
+
+    assert!(calculator.calculate(r#"{"a": "1"}"#).is_err());
+    assert!(calculator.calculate(r#"{"a": "1", "b": "2", "c": "2"}"#).is_err());
+}
+
+#[test]
+pub fn test_first_unsatisfied() {
+    use std::fs;
+    use std::io::Cursor;
+    use plonkit::reader;
+
+    let r1cs = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/mycircuit.r1cs")).expect("fail");
+    let wtns = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/circoms/witness.wtns")).expect("fail");
+    let (r1cs, _) = reader::load_r1cs_from_bin(Cursor::new(r1cs));
+    let mut witness = reader::load_witness_from_array::<Bn256>(wtns).expect("fail");
+    assert_eq!(first_unsatisfied(&r1cs, witness.as_slice()), None);
+
+    // bump the output so c != a * b
+    witness[1].add_assign(&Fr::one());
+    assert_eq!(first_unsatisfied(&r1cs, witness.as_slice()), Some(0));
+}